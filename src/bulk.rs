@@ -0,0 +1,321 @@
+use std::cmp::Ordering;
+use std::mem;
+
+use crate::node::{BTreeRules, Internal, Item, Leaf, Node};
+use crate::{BTree, BTreeError, DEFAULT_DEGREE};
+
+// how many nodes to spread `slots` over (items + 1 for leaves, children for internal nodes):
+// as many as it takes to hold `per` items each, but never so many that one drops under `min`
+fn layout(slots: usize, per: usize, min: usize) -> usize {
+    slots.div_ceil(per + 1).min(slots / (min + 1)).max(1)
+}
+
+// and how many slots each of them gets, differing by one at most
+fn shares(slots: usize, nodes: usize) -> impl Iterator<Item = usize> {
+    (0..nodes).map(move |node| slots / nodes + usize::from(node < slots % nodes))
+}
+
+pub(crate) fn build<K, V>(items: Vec<Item<K, V>>, rules: &BTreeRules, per: usize) -> Node<K, V> {
+    // Bottom up: deal the items out into leaves, holding back one between each pair of leaves as
+    // their separator, then deal the leaves and separators out into parents the same way, and
+    // so on until a level comes out as a single node.
+    let slots = items.len() + 1;
+    let mut items = items.into_iter();
+    let nodes = layout(slots, per, rules.minkeys);
+    let mut level = Vec::with_capacity(nodes);
+    let mut separators = Vec::with_capacity(nodes - 1);
+    for share in shares(slots, nodes) {
+        level.push(Node::Leaf(Leaf { items: items.by_ref().take(share - 1).collect() }));
+        separators.extend(items.next());
+    }
+
+    while level.len() > 1 {
+        let slots = level.len();
+        let nodes = layout(slots, per, rules.minkeys);
+        let mut below = level.into_iter();
+        let mut above = separators.into_iter();
+        level = Vec::with_capacity(nodes);
+        separators = Vec::with_capacity(nodes - 1);
+        for share in shares(slots, nodes) {
+            let children: Vec<Node<K, V>> = below.by_ref().take(share).collect();
+            let sizes = if rules.counted { children.iter().map(Node::size).collect() } else { Vec::new() };
+            let items = above.by_ref().take(share - 1).collect();
+            level.push(Node::Internal(Internal { items, children, sizes }));
+            separators.extend(above.next());
+        }
+    }
+    level.pop().unwrap()
+}
+
+fn per_node(rules: &BTreeRules, fill: f64) -> usize {
+    let per = (fill * rules.maxkeys as f64).round() as usize;
+    assert!(
+        fill <= 1.0 && per >= rules.minkeys.max(1),
+        "a fill factor of {fill} packs {per} keys per node, outside {}..={} for degree {}",
+        rules.minkeys.max(1),
+        rules.maxkeys,
+        rules.degree,
+    );
+    per
+}
+
+// sorted input, checked as it goes: every key strictly after the one before (and after `after`)
+fn strictly_ascending<K, V, I>(entries: I, after: Option<&K>) -> Result<Vec<Item<K, V>>, BTreeError>
+where
+    K: Ord,
+    I: IntoIterator<Item = (K, V)>,
+{
+    let mut items: Vec<Item<K, V>> = Vec::new();
+    for (key, value) in entries {
+        let last = items.last().map(|item| &item.key).or(after);
+        if last.is_some_and(|last| *last >= key) {
+            return Err(BTreeError::UnorderedKey);
+        }
+        items.push(Item { key, value });
+    }
+    Ok(items)
+}
+
+// unsorted input, sorted: stable, so of any repeats the last value wins but the first key stays,
+// just as inserting them one by one would leave it
+fn sorted<K: Ord, V>(mut entries: Vec<(K, V)>) -> Vec<Item<K, V>> {
+    entries.sort_by(|(left, _), (right, _)| left.cmp(right));
+    let mut items: Vec<Item<K, V>> = Vec::with_capacity(entries.len());
+    for (key, value) in entries {
+        match items.last_mut() {
+            Some(last) if last.key == key => last.value = value,
+            _ => items.push(Item { key, value }),
+        }
+    }
+    items
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Ord,
+{
+    /// Builds a tree from entries already in strictly ascending key order,
+    /// packing every node full.
+    ///
+    /// Much faster than inserting one at a time, and leaves no half-empty
+    /// nodes behind. Fails with [`BTreeError::UnorderedKey`] if a key isn't
+    /// strictly greater than the one before it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `degree` is less than 2.
+    pub fn from_sorted_iter<I>(degree: usize, entries: I) -> Result<Self, BTreeError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        Self::from_sorted_iter_with_fill(degree, 1.0, entries)
+    }
+
+    /// Like [`from_sorted_iter`](Self::from_sorted_iter), but packs each
+    /// node to `fill` (a fraction of the most keys a node can hold), leaving
+    /// room for later inserts before anything has to split.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `degree` is less than 2, or if `fill` would pack nodes with
+    /// fewer keys than the degree's minimum or more than its maximum.
+    pub fn from_sorted_iter_with_fill<I>(degree: usize, fill: f64, entries: I) -> Result<Self, BTreeError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut tree = BTree::new(degree);
+        let per = per_node(&tree.rules, fill);
+        tree.load(strictly_ascending(entries, None)?, per);
+        Ok(tree)
+    }
+
+    /// Appends entries that are in strictly ascending key order and all
+    /// greater than every key already in the tree.
+    ///
+    /// A batch at least as big as the tree is bulk loaded together with what's
+    /// already there; a smaller one is bulk loaded on its own and grafted onto
+    /// the tree's right edge, without any key searches. Fails with [`BTreeError::UnorderedKey`], leaving the tree
+    /// untouched, if the keys are out of order.
+    pub fn append_sorted<I>(&mut self, entries: I) -> Result<(), BTreeError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let _span = tracing::debug_span!("btree.append_sorted", len = self.len).entered();
+        let items = strictly_ascending(entries, self.last_key_value().map(|(key, _)| key))?;
+        if items.len() >= self.len {
+            let all = self.take().into_iter().map(|(key, value)| Item { key, value }).chain(items).collect();
+            self.load(all, self.rules.maxkeys);
+            return Ok(());
+        }
+        if !items.is_empty() {
+            let mut batch = BTree { root: Box::new(Node::new(&self.rules)), len: 0, rules: self.rules };
+            batch.load(items, self.rules.maxkeys);
+            self.concat(batch);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn take(&mut self) -> BTree<K, V> {
+        // everything out, leaving an empty tree with the same rules behind
+        let rules = self.rules;
+        mem::replace(self, BTree { root: Box::new(Node::new(&rules)), len: 0, rules })
+    }
+
+    pub(crate) fn load(&mut self, items: Vec<Item<K, V>>, per: usize) {
+        self.len = items.len();
+        *self.root = build(items, &self.rules, per);
+        tracing::debug!(len = self.len, height = self.height(), per, "bulk loaded");
+    }
+}
+
+/// Builds a tree of the default degree by sorting the entries and bulk
+/// loading them. Of repeated keys the last value wins, as with repeated
+/// [`insert`](BTree::insert)s.
+impl<K, V> FromIterator<(K, V)> for BTree<K, V>
+where
+    K: Ord,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> Self {
+        let mut tree = BTree::new(DEFAULT_DEGREE);
+        tree.extend(entries);
+        tree
+    }
+}
+
+/// Inserts every entry. A batch at least as big as the tree is sorted and
+/// merged with the tree's entries into a freshly bulk loaded tree instead.
+impl<K, V> Extend<(K, V)> for BTree<K, V>
+where
+    K: Ord,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, entries: I) {
+        let _span = tracing::debug_span!("btree.extend", len = self.len).entered();
+        let batch: Vec<(K, V)> = entries.into_iter().collect();
+        if batch.len() < self.len {
+            for (key, value) in batch {
+                self.insert(key, value);
+            }
+            return;
+        }
+
+        let mut old = self.take().into_iter().peekable();
+        let mut new = sorted(batch).into_iter().peekable();
+        let mut merged = Vec::with_capacity(old.len() + new.len());
+        loop {
+            let order = match (old.peek(), new.peek()) {
+                (Some((old, _)), Some(new)) => old.cmp(&new.key),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            let item = match order {
+                Ordering::Less => old.next().map(|(key, value)| Item { key, value }),
+                Ordering::Greater => new.next(),
+                // a key in both keeps the tree's copy of the key and takes the batch's value
+                Ordering::Equal => old.next().zip(new.next()).map(|((key, _), new)| Item { key, value: new.value }),
+            };
+            merged.extend(item);
+        }
+        self.load(merged, self.rules.maxkeys);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DEGREES;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn sorted_input_loads_into_a_valid_packed_tree() {
+        for degree in DEGREES {
+            for n in [0, 1, 2, 3, 4, 5, 17, 100, 1000] {
+                let btree = BTree::from_sorted_iter(degree, (0..n).map(|key| (key, key * 10))).unwrap();
+                btree.validate().unwrap();
+                assert_eq!(btree.len(), n as usize);
+                assert!(btree.iter().map(|(key, value)| (*key, *value)).eq((0..n).map(|key| (key, key * 10))));
+
+                // full nodes fan out as far as they can, so nothing inserted one by one is shorter
+                let mut inserted = BTree::new(degree);
+                for key in 0..n {
+                    inserted.insert(key, key * 10);
+                }
+                assert!(btree.height() <= inserted.height(), "{n} keys at degree {degree}");
+            }
+        }
+    }
+
+    #[test]
+    fn fill_leaves_room_to_insert() {
+        for degree in DEGREES {
+            let half = BTree::from_sorted_iter_with_fill(degree, 0.5, (0..500).map(|key| (key * 2, ()))).unwrap();
+            let full = BTree::from_sorted_iter_with_fill(degree, 1.0, (0..500).map(|key| (key * 2, ()))).unwrap();
+            half.validate().unwrap();
+            full.validate().unwrap();
+            assert!(half.height() >= full.height());
+
+            let mut half = half;
+            for key in 0..500 {
+                half.insert(key * 2 + 1, ());
+            }
+            half.validate().unwrap();
+            assert_eq!(half.len(), 1000);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "fill factor")]
+    fn fill_under_the_minimum_panics() {
+        let _ = BTree::from_sorted_iter_with_fill(8, 0.1, (0..10).map(|key| (key, ())));
+    }
+
+    #[test]
+    fn unsorted_input_is_refused() {
+        assert_eq!(BTree::from_sorted_iter(3, [(1, ()), (3, ()), (2, ())]).err(), Some(BTreeError::UnorderedKey));
+        assert_eq!(BTree::from_sorted_iter(3, [(1, ()), (1, ())]).err(), Some(BTreeError::UnorderedKey));
+
+        let mut btree = BTree::from_sorted_iter(3, (0..10).map(|key| (key, ()))).unwrap();
+        assert_eq!(btree.append_sorted([(20, ()), (9, ())]), Err(BTreeError::UnorderedKey));
+        assert_eq!(btree.append_sorted([(9, ())]), Err(BTreeError::UnorderedKey));
+        assert_eq!(btree.len(), 10);
+        btree.validate().unwrap();
+    }
+
+    #[test]
+    fn append_sorted_small_and_large_batches() {
+        for degree in DEGREES {
+            let mut btree = BTree::with_subtree_sizes(degree);
+            let mut expected = BTreeMap::new();
+            // the first batch outnumbers the (empty) tree and reloads it; the small ones walk
+            // the right edge; the last outnumbers the tree again
+            for batch in [0..300, 300..310, 310..311, 311..400, 400..1000] {
+                btree.append_sorted(batch.clone().map(|key| (key, key))).unwrap();
+                expected.extend(batch.map(|key| (key, key)));
+                btree.validate().unwrap();
+                assert!(btree.iter().eq(expected.iter()));
+            }
+            assert_eq!(btree.nth(500), Some((&500, &500)));
+        }
+    }
+
+    #[test]
+    fn collect_and_extend_keep_the_last_value() {
+        let entries: Vec<(i32, i32)> = (0..2000).map(|i| ((i * 7919) % 1009, i)).collect();
+        let btree: BTree<i32, i32> = entries.iter().copied().collect();
+        let expected: BTreeMap<i32, i32> = entries.iter().copied().collect();
+        btree.validate().unwrap();
+        assert!(btree.iter().eq(expected.iter()));
+
+        for degree in DEGREES {
+            let mut btree = BTree::new(degree);
+            let mut expected = BTreeMap::new();
+            // a batch at least as big as the tree merges and reloads; a smaller one inserts
+            for batch in [&entries[..500], &entries[500..600], &entries[600..]] {
+                btree.extend(batch.iter().copied());
+                expected.extend(batch.iter().copied());
+                btree.validate().unwrap();
+                assert!(btree.iter().eq(expected.iter()));
+            }
+        }
+    }
+}
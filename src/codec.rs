@@ -0,0 +1,122 @@
+/// How the keys and values of a [`DiskBTree`](crate::DiskBTree) turn into
+/// bytes in a page, and back.
+///
+/// Implemented for the integer types, `bool`, `()`, `String` and `Vec<u8>`.
+/// Integers are little-endian and fixed-width; strings and byte vectors carry
+/// a `u32` length prefix.
+pub trait Codec: Sized {
+    /// How many bytes every value encodes to, if that never changes. A
+    /// [`DiskBTree`](crate::DiskBTree) whose keys and values both have a
+    /// width can size its degree to the page; anything else is sized by
+    /// bytes.
+    const WIDTH: Option<usize> = None;
+
+    /// Appends the encoded form of `self` to `out`.
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// Reads one value off the front of `input`, advancing it past the bytes
+    /// used. `None` if `input` doesn't start with a whole, valid value.
+    fn decode(input: &mut &[u8]) -> Option<Self>;
+}
+
+// the next `n` bytes of `input`, if there are that many
+pub(crate) fn take<'a>(input: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    let (taken, rest) = input.split_at_checked(n)?;
+    *input = rest;
+    Some(taken)
+}
+
+macro_rules! integers {
+    ($($int:ty),*) => {$(
+        impl Codec for $int {
+            const WIDTH: Option<usize> = Some(size_of::<$int>());
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+            fn decode(input: &mut &[u8]) -> Option<Self> {
+                let bytes = take(input, size_of::<$int>())?;
+                Some(<$int>::from_le_bytes(bytes.try_into().unwrap()))
+            }
+        }
+    )*};
+}
+integers!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Codec for bool {
+    const WIDTH: Option<usize> = Some(1);
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(u8::from(*self));
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match take(input, 1)? {
+            [0] => Some(false),
+            [1] => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl Codec for () {
+    const WIDTH: Option<usize> = Some(0);
+    fn encode(&self, _: &mut Vec<u8>) {}
+    fn decode(_: &mut &[u8]) -> Option<Self> {
+        Some(())
+    }
+}
+
+impl Codec for Vec<u8> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        out.extend_from_slice(self);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let len = u32::decode(input)?;
+        Some(take(input, len as usize)?.to_vec())
+    }
+}
+
+impl Codec for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        String::from_utf8(Vec::decode(input)?).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip<T: Codec + PartialEq + std::fmt::Debug>(value: T) {
+        let mut bytes = Vec::new();
+        value.encode(&mut bytes);
+        bytes.push(0xAA);
+        let mut input = &bytes[..];
+        assert_eq!(T::decode(&mut input), Some(value));
+        // only its own bytes were taken
+        assert_eq!(input, [0xAA]);
+    }
+
+    #[test]
+    fn values_round_trip() {
+        round_trip(0u8);
+        round_trip(-1i32);
+        round_trip(u64::MAX);
+        round_trip(i128::MIN);
+        round_trip(true);
+        round_trip(());
+        round_trip(String::from("Nerevar's Ring"));
+        round_trip(String::new());
+        round_trip(vec![1u8, 2, 3]);
+    }
+
+    #[test]
+    fn short_or_bad_input_is_refused() {
+        assert_eq!(u32::decode(&mut &[1, 2, 3][..]), None);
+        assert_eq!(bool::decode(&mut &[2][..]), None);
+        assert_eq!(String::decode(&mut &[5, 0, 0, 0, b'a'][..]), None);
+        assert_eq!(String::decode(&mut &[1, 0, 0, 0, 0xFF][..]), None);
+    }
+}
@@ -0,0 +1,590 @@
+use std::borrow::Borrow;
+use std::mem;
+
+use crate::node::{Internal, Item, Node};
+use crate::{BTree, BTreeError};
+
+// Spots in the tree are paths: a child index for each level stepped down, then an index into the
+// last node's items. Pointing at an item, that last node can be any node; pointing at a gap
+// between two items, it is always a leaf and the index is an edge (0 ..= items).
+
+fn node_at<'n, K, V>(mut node: &'n Node<K, V>, steps: &[usize]) -> &'n Node<K, V> {
+    for &step in steps {
+        node = &node.children()[step];
+    }
+    node
+}
+
+fn node_at_mut<'n, K, V>(mut node: &'n mut Node<K, V>, steps: &[usize]) -> &'n mut Node<K, V> {
+    for &step in steps {
+        node = match node {
+            Node::Internal(internal) => &mut internal.children[step],
+            Node::Leaf(_) => unreachable!("paths only step down through internal nodes"),
+        };
+    }
+    node
+}
+
+pub(crate) fn item_at<'n, K, V>(root: &'n Node<K, V>, path: &[usize]) -> &'n Item<K, V> {
+    let (&index, steps) = path.split_last().unwrap();
+    &node_at(root, steps).items()[index]
+}
+
+pub(crate) fn item_at_mut<'n, K, V>(root: &'n mut Node<K, V>, path: &[usize]) -> &'n mut Item<K, V> {
+    let (&index, steps) = path.split_last().unwrap();
+    &mut node_at_mut(root, steps).items_mut()[index]
+}
+
+// grow `path` down to a leaf edge, hugging the left or right side of whatever it points into
+fn descend_first<K, V>(root: &Node<K, V>, path: &mut Vec<usize>) {
+    while let Node::Internal(_) = node_at(root, path) {
+        path.push(0);
+    }
+    path.push(0);
+}
+fn descend_last<K, V>(root: &Node<K, V>, path: &mut Vec<usize>) {
+    loop {
+        match node_at(root, path) {
+            Node::Internal(internal) => path.push(internal.children.len() - 1),
+            Node::Leaf(leaf) => return path.push(leaf.items.len()),
+        }
+    }
+}
+
+// item `i` of an internal node sits between children `i` and `i + 1`, so the gaps either side
+// of it are at the edge of those subtrees
+fn gap_after<K, V>(root: &Node<K, V>, mut item: Vec<usize>) -> Vec<usize> {
+    *item.last_mut().unwrap() += 1;
+    if let Node::Internal(_) = node_at(root, &item[..item.len() - 1]) {
+        descend_first(root, &mut item);
+    }
+    item
+}
+fn gap_before<K, V>(root: &Node<K, V>, mut item: Vec<usize>) -> Vec<usize> {
+    if let Node::Internal(_) = node_at(root, &item[..item.len() - 1]) {
+        descend_last(root, &mut item);
+    }
+    item
+}
+
+// and coming back up out of a subtree, child `c` is followed by item `c` and preceded by `c - 1`
+fn next_item<K, V>(root: &Node<K, V>, mut gap: Vec<usize>) -> Option<Vec<usize>> {
+    while let Some((&index, steps)) = gap.split_last() {
+        if index < node_at(root, steps).items().len() {
+            return Some(gap);
+        }
+        gap.pop();
+    }
+    None
+}
+fn prev_item(mut gap: Vec<usize>) -> Option<Vec<usize>> {
+    while let Some(index) = gap.last_mut() {
+        if *index > 0 {
+            *index -= 1;
+            return Some(gap);
+        }
+        gap.pop();
+    }
+    None
+}
+
+fn first_gap<K, V>(root: &Node<K, V>) -> Vec<usize> {
+    let mut gap = Vec::new();
+    descend_first(root, &mut gap);
+    gap
+}
+pub(crate) fn last_gap<K, V>(root: &Node<K, V>) -> Vec<usize> {
+    let mut gap = Vec::new();
+    descend_last(root, &mut gap);
+    gap
+}
+
+// the ghost (`None`) sits past both ends, so stepping off it wraps round to the first or last item
+pub(crate) fn next_of<K, V>(root: &Node<K, V>, current: Option<Vec<usize>>) -> Option<Vec<usize>> {
+    let gap = match current {
+        Some(item) => gap_after(root, item),
+        None => first_gap(root),
+    };
+    next_item(root, gap)
+}
+fn prev_of<K, V>(root: &Node<K, V>, current: Option<Vec<usize>>) -> Option<Vec<usize>> {
+    let gap = match current {
+        Some(item) => gap_before(root, item),
+        None => last_gap(root),
+    };
+    prev_item(gap)
+}
+
+pub(crate) fn search<K, V, Q>(root: &Node<K, V>, key: &Q) -> Result<Vec<usize>, Vec<usize>>
+where
+    K: Borrow<Q> + Ord,
+    Q: Ord + ?Sized,
+{
+    // the same descent as `get`, but remembering the way down: the path to the item if it's
+    // there, otherwise the leaf edge where it would go
+    let mut path = Vec::new();
+    let mut node = root;
+    loop {
+        let (index, found) = node.binary_search(key);
+        path.push(index);
+        if found {
+            return Ok(path);
+        }
+        match node {
+            Node::Leaf(_) => return Err(path),
+            Node::Internal(internal) => node = &internal.children[index],
+        }
+    }
+}
+
+pub(crate) fn seek<K, V, Q>(root: &Node<K, V>, key: &Q, upper: bool) -> Option<Vec<usize>>
+where
+    K: Borrow<Q> + Ord,
+    Q: Ord + ?Sized,
+{
+    // a missing key's bound is whichever item is on the asked-for side of its gap
+    match search(root, key) {
+        Ok(path) => Some(path),
+        Err(gap) if upper => prev_item(gap),
+        Err(gap) => next_item(root, gap),
+    }
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Ord,
+{
+    pub(crate) fn insert_at(&mut self, mut gap: Vec<usize>, item: Item<K, V>) -> Vec<usize> {
+        // hands back the path to the new item
+        if self.root.items().len() >= self.rules.maxkeys {
+            let median = self.root_split();
+            gap.insert(0, 0);
+            if gap[1] > median {
+                gap[0] = 1;
+                gap[1] -= median + 1;
+            }
+        }
+        self.root.make_room(&mut gap, &self.rules, 0);
+        let (&edge, steps) = gap.split_last().unwrap();
+        node_at_mut(&mut self.root, steps).items_mut().insert(edge, item);
+        self.len += 1;
+        gap
+    }
+
+    pub(crate) fn remove_at(&mut self, mut path: Vec<usize>) -> (Item<K, V>, Vec<usize>, bool) {
+        // hands back the item, the gap it left and whether it was swapped down from an internal node
+        let (&index, steps) = path.split_last().unwrap();
+        let swapped = match node_at_mut(&mut self.root, steps) {
+            Node::Leaf(_) => false,
+            Node::Internal(Internal { items, children, .. }) => {
+                // trade places with the successor, so the item to go is always in a leaf
+                let mut node = &mut children[index + 1];
+                while let Node::Internal(internal) = node {
+                    node = &mut internal.children[0];
+                }
+                mem::swap(&mut items[index], &mut node.items_mut()[0]);
+                true
+            }
+        };
+        if swapped {
+            *path.last_mut().unwrap() += 1;
+            descend_first(&self.root, &mut path);
+        }
+        let item = self.root.delete_at(&mut path, &self.rules, 0);
+        self.len -= 1;
+        if self.root_shrink() {
+            path.remove(0);
+        }
+        (item, path, swapped)
+    }
+}
+
+/// A read-only cursor over a [`BTree`], from [`BTree::lower_bound`] or
+/// [`BTree::upper_bound`].
+///
+/// The cursor points at an entry, or at a "ghost" position past both ends of
+/// the tree: moving forward off the last entry lands on the ghost, and moving
+/// forward again lands on the first entry.
+pub struct Cursor<'a, K, V> {
+    pub(crate) root: &'a Node<K, V>,
+    pub(crate) current: Option<Vec<usize>>,
+}
+
+impl<'a, K, V> Cursor<'a, K, V> {
+    /// The entry the cursor points at, or `None` on the ghost.
+    pub fn key_value(&self) -> Option<(&'a K, &'a V)> {
+        let item = item_at(self.root, self.current.as_ref()?);
+        Some((&item.key, &item.value))
+    }
+
+    /// The key the cursor points at, or `None` on the ghost.
+    pub fn key(&self) -> Option<&'a K> {
+        self.key_value().map(|(key, _)| key)
+    }
+
+    /// The value the cursor points at, or `None` on the ghost.
+    pub fn value(&self) -> Option<&'a V> {
+        self.key_value().map(|(_, value)| value)
+    }
+
+    /// The entry after the cursor, without moving.
+    pub fn peek_next(&self) -> Option<(&'a K, &'a V)> {
+        let item = item_at(self.root, &next_of(self.root, self.current.clone())?);
+        Some((&item.key, &item.value))
+    }
+
+    /// The entry before the cursor, without moving.
+    pub fn peek_prev(&self) -> Option<(&'a K, &'a V)> {
+        let item = item_at(self.root, &prev_of(self.root, self.current.clone())?);
+        Some((&item.key, &item.value))
+    }
+
+    /// Moves to the next entry.
+    pub fn move_next(&mut self) {
+        self.current = next_of(self.root, self.current.take());
+    }
+
+    /// Moves to the previous entry.
+    pub fn move_prev(&mut self) {
+        self.current = prev_of(self.root, self.current.take());
+    }
+}
+
+/// A cursor over a [`BTree`] that can also change it, from
+/// [`BTree::lower_bound_mut`] or [`BTree::upper_bound_mut`].
+///
+/// Inserts and removals follow the cursor's own path down the tree instead of
+/// searching for the key again, doing the same proactive splits and merges as
+/// [`BTree::insert`] and [`BTree::remove`].
+pub struct CursorMut<'a, K, V> {
+    pub(crate) tree: &'a mut BTree<K, V>,
+    pub(crate) current: Option<Vec<usize>>,
+}
+
+impl<'a, K, V> CursorMut<'a, K, V> {
+    /// A read-only view of the tree at this cursor's position.
+    pub fn as_cursor(&self) -> Cursor<'_, K, V> {
+        Cursor { root: &self.tree.root, current: self.current.clone() }
+    }
+
+    /// The entry the cursor points at, or `None` on the ghost.
+    pub fn key_value(&self) -> Option<(&K, &V)> {
+        let item = item_at(&self.tree.root, self.current.as_ref()?);
+        Some((&item.key, &item.value))
+    }
+
+    /// The key the cursor points at, or `None` on the ghost.
+    pub fn key(&self) -> Option<&K> {
+        self.key_value().map(|(key, _)| key)
+    }
+
+    /// The value the cursor points at, or `None` on the ghost.
+    pub fn value_mut(&mut self) -> Option<&mut V> {
+        Some(&mut item_at_mut(&mut self.tree.root, self.current.as_ref()?).value)
+    }
+
+    /// The entry after the cursor, without moving.
+    pub fn peek_next(&self) -> Option<(&K, &V)> {
+        let item = item_at(&self.tree.root, &next_of(&self.tree.root, self.current.clone())?);
+        Some((&item.key, &item.value))
+    }
+
+    /// The entry before the cursor, without moving.
+    pub fn peek_prev(&self) -> Option<(&K, &V)> {
+        let item = item_at(&self.tree.root, &prev_of(&self.tree.root, self.current.clone())?);
+        Some((&item.key, &item.value))
+    }
+
+    /// Moves to the next entry.
+    pub fn move_next(&mut self) {
+        self.current = next_of(&self.tree.root, self.current.take());
+    }
+
+    /// Moves to the previous entry.
+    pub fn move_prev(&mut self) {
+        self.current = prev_of(&self.tree.root, self.current.take());
+    }
+}
+
+impl<'a, K, V> CursorMut<'a, K, V>
+where
+    K: Ord,
+{
+    /// Inserts an entry just after the cursor (at the front, from the ghost),
+    /// leaving the cursor where it is.
+    ///
+    /// Fails with [`BTreeError::UnorderedKey`] unless `key` sorts strictly
+    /// between the current entry and the next one.
+    pub fn insert_after(&mut self, key: K, value: V) -> Result<(), BTreeError> {
+        let _span = tracing::debug_span!("btree.cursor.insert_after", len = self.tree.len).entered();
+        if self.key().is_some_and(|current| *current >= key) || self.peek_next().is_some_and(|(next, _)| *next <= key) {
+            return Err(BTreeError::UnorderedKey);
+        }
+        let Some(current) = self.current.take() else {
+            self.tree.insert_at(first_gap(&self.tree.root), Item { key, value });
+            return Ok(());
+        };
+        let inserted = self.tree.insert_at(gap_after(&self.tree.root, current), Item { key, value });
+        // splits may have moved the current entry, but it is still the one just before ours
+        self.current = prev_of(&self.tree.root, Some(inserted));
+        Ok(())
+    }
+
+    /// Inserts an entry just before the cursor (at the back, from the ghost),
+    /// leaving the cursor where it is.
+    ///
+    /// Fails with [`BTreeError::UnorderedKey`] unless `key` sorts strictly
+    /// between the previous entry and the current one.
+    pub fn insert_before(&mut self, key: K, value: V) -> Result<(), BTreeError> {
+        let _span = tracing::debug_span!("btree.cursor.insert_before", len = self.tree.len).entered();
+        if self.key().is_some_and(|current| *current <= key) || self.peek_prev().is_some_and(|(prev, _)| *prev >= key) {
+            return Err(BTreeError::UnorderedKey);
+        }
+        let Some(current) = self.current.take() else {
+            self.tree.insert_at(last_gap(&self.tree.root), Item { key, value });
+            return Ok(());
+        };
+        let inserted = self.tree.insert_at(gap_before(&self.tree.root, current), Item { key, value });
+        self.current = next_of(&self.tree.root, Some(inserted));
+        Ok(())
+    }
+
+    /// Removes the entry the cursor points at and moves on to the next one.
+    /// Does nothing on the ghost.
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        let _span = tracing::debug_span!("btree.cursor.remove_current", len = self.tree.len).entered();
+        let (item, gap, swapped) = self.tree.remove_at(self.current.take()?);
+        // a removal from an internal node left its successor in its place, just before the gap
+        self.current = if swapped { prev_item(gap) } else { next_item(&self.tree.root, gap) };
+        Some((item.key, item.value))
+    }
+}
+
+/// A lazy iterator that removes, in key order, the entries its predicate
+/// picks, and yields them. Created by [`BTree::extract_if`].
+///
+/// Entries it hasn't reached yet stay in the tree if it's dropped early.
+pub struct ExtractIf<'a, K, V, F> {
+    pub(crate) tree: &'a mut BTree<K, V>,
+    // the next item to offer the predicate
+    pub(crate) current: Option<Vec<usize>>,
+    pub(crate) pred: F,
+    // entered for each step, so the removals it makes are reported under it
+    pub(crate) span: tracing::Span,
+}
+
+impl<K, V, F> Iterator for ExtractIf<'_, K, V, F>
+where
+    K: Ord,
+    F: FnMut(&K, &mut V) -> bool,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        let _span = self.span.enter();
+        while let Some(path) = self.current.take() {
+            let item = item_at_mut(&mut self.tree.root, &path);
+            if (self.pred)(&item.key, &mut item.value) {
+                // straight down the path we're on, no search, and on from wherever it left us
+                let (item, gap, swapped) = self.tree.remove_at(path);
+                self.current = if swapped { prev_item(gap) } else { next_item(&self.tree.root, gap) };
+                return Some((item.key, item.value));
+            }
+            self.current = next_of(&self.tree.root, Some(path));
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.tree.len))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DEGREES;
+    use std::collections::BTreeMap;
+
+    // every other key from 0 up to (not including) `n`, inserted out of order
+    fn evens(degree: usize, n: i32) -> BTree<i32, i32> {
+        let mut btree = BTree::new(degree);
+        for key in (0..n / 2).map(|i| (i * 37) % (n / 2) * 2) {
+            btree.insert(key, key * 10);
+        }
+        btree
+    }
+
+    #[test]
+    fn bounds_land_either_side_of_missing_keys() {
+
+        for degree in DEGREES {
+            let btree = evens(degree, 400);
+            for key in -1..401 {
+                let at_least = btree.lower_bound(&key).key().copied();
+                let at_most = btree.upper_bound(&key).key().copied();
+                let expected_at_least = if key < 400 - 1 { Some(key.max(0) + key.max(0) % 2) } else { None };
+                let expected_at_most = if key >= 0 { Some((key - key % 2).min(398)) } else { None };
+                assert_eq!(at_least, expected_at_least, "lower_bound({key}) at degree {degree}");
+                assert_eq!(at_most, expected_at_most, "upper_bound({key}) at degree {degree}");
+            }
+        }
+    }
+
+    #[test]
+    fn walks_both_ways_and_wraps_through_the_ghost() {
+
+        for degree in DEGREES {
+            let btree = evens(degree, 600);
+            let mut cursor = btree.lower_bound(&1000);
+            assert_eq!(cursor.key(), None);
+            assert_eq!(cursor.peek_next(), Some((&0, &0)));
+            assert_eq!(cursor.peek_prev(), Some((&598, &5980)));
+
+            let mut forward = Vec::new();
+            cursor.move_next();
+            while let Some(&key) = cursor.key() {
+                assert_eq!(cursor.peek_next().map(|(next, _)| *next), (key < 598).then_some(key + 2));
+                forward.push(key);
+                cursor.move_next();
+            }
+            assert!(forward.into_iter().eq((0..600).step_by(2)));
+
+            let mut backward = Vec::new();
+            cursor.move_prev();
+            while let Some((&key, &value)) = cursor.key_value() {
+                assert_eq!(value, key * 10);
+                assert_eq!(cursor.peek_prev().map(|(prev, _)| *prev), (key > 0).then(|| key - 2));
+                backward.push(key);
+                cursor.move_prev();
+            }
+            assert!(backward.into_iter().eq((0..600).step_by(2).rev()));
+        }
+        let empty = BTree::<i32, ()>::new(2);
+        let mut cursor = empty.lower_bound(&0);
+        cursor.move_next();
+        assert_eq!((cursor.key(), cursor.peek_next(), cursor.peek_prev()), (None, None, None));
+    }
+
+    #[test]
+    fn inserts_beside_the_cursor() {
+
+        for degree in DEGREES {
+            let mut btree = evens(degree, 400);
+            let mut cursor = btree.lower_bound_mut(&0);
+            // fill in every odd key on the way past, growing the tree under the cursor
+            while let Some(&key) = cursor.key() {
+                cursor.insert_after(key + 1, -1).unwrap();
+                assert_eq!(cursor.key(), Some(&key));
+                assert_eq!(cursor.peek_next(), Some((&(key + 1), &-1)));
+                cursor.move_next();
+                cursor.move_next();
+            }
+            // and from the ghost, before means at the back
+            cursor.insert_before(1000, 1).unwrap();
+            assert_eq!(cursor.key(), None);
+            assert_eq!(cursor.peek_prev(), Some((&1000, &1)));
+            btree.validate().unwrap();
+            assert!(btree.keys().copied().eq((0..400).chain([1000])));
+
+            let mut btree = evens(degree, 400);
+            let mut cursor = btree.upper_bound_mut(&398);
+            while let Some(&key) = cursor.key() {
+                cursor.insert_before(key - 1, -1).unwrap();
+                assert_eq!(cursor.peek_prev(), Some((&(key - 1), &-1)));
+                *cursor.value_mut().unwrap() += 1;
+                cursor.move_prev();
+                cursor.move_prev();
+            }
+            cursor.insert_after(-1000, 1).unwrap();
+            btree.validate().unwrap();
+            assert!(btree.keys().copied().eq([-1000].into_iter().chain(-1..399)));
+            assert_eq!(btree.get(&6), Some(&61));
+        }
+    }
+
+    #[test]
+    fn insert_refuses_keys_out_of_order() {
+
+        let mut btree = evens(2, 20);
+        let mut cursor = btree.lower_bound_mut(&8);
+        assert_eq!(cursor.insert_after(8, 0), Err(BTreeError::UnorderedKey));
+        assert_eq!(cursor.insert_after(10, 0), Err(BTreeError::UnorderedKey));
+        assert_eq!(cursor.insert_before(5, 0), Err(BTreeError::UnorderedKey));
+        assert_eq!(cursor.insert_before(6, 0), Err(BTreeError::UnorderedKey));
+        cursor.move_prev();
+        cursor.move_prev();
+        cursor.move_prev();
+        cursor.move_prev();
+        cursor.move_prev();
+        assert_eq!(cursor.key(), None);
+        assert_eq!(cursor.insert_after(0, 0), Err(BTreeError::UnorderedKey));
+        assert_eq!(cursor.insert_before(18, 0), Err(BTreeError::UnorderedKey));
+        assert_eq!(btree.len(), 10);
+        btree.validate().unwrap();
+    }
+
+    #[test]
+    fn removes_under_the_cursor() {
+
+        for degree in DEGREES {
+            for start in [0, 2, 202, 398] {
+                let mut btree = evens(degree, 400);
+                let mut expected: BTreeMap<i32, i32> = btree.iter().map(|(&key, &value)| (key, value)).collect();
+
+                // thin out every other entry from `start`, wrapping round through the ghost once
+                let mut cursor = btree.lower_bound_mut(&start);
+                for _ in 0..100 {
+                    let Some((key, value)) = cursor.remove_current() else {
+                        cursor.move_next();
+                        continue;
+                    };
+                    assert_eq!(expected.remove(&key), Some(value));
+                    if let Some(next) = cursor.key() {
+                        assert_eq!(expected.range(key..).next().map(|(key, _)| key), Some(next));
+                    }
+                    cursor.move_next();
+                }
+                btree.validate().unwrap();
+                assert!(btree.iter().eq(expected.iter()));
+
+                // then everything that's left
+                let mut cursor = btree.lower_bound_mut(&0);
+                while cursor.remove_current().is_some() {}
+                assert!(btree.is_empty());
+                btree.validate().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn extract_if_removes_only_what_it_picks() {
+
+        for degree in DEGREES {
+            let mut btree = evens(degree, 600);
+            let mut expected: BTreeMap<i32, i32> = (0..300).map(|i| (i * 2, i * 20)).collect();
+            let mut pick = |key: &i32, value: &mut i32| {
+                *value -= 1;
+                key % 3 == 0
+            };
+            let extracted: Vec<(i32, i32)> = btree.extract_if(&mut pick).collect();
+            let expected_extracted: Vec<(i32, i32)> = expected.extract_if(.., &mut pick).collect();
+            assert_eq!(extracted, expected_extracted);
+            btree.validate().unwrap();
+            assert!(btree.iter().eq(expected.iter()));
+        }
+    }
+
+    #[test]
+    fn extract_if_dropped_early_leaves_the_rest() {
+
+        for degree in DEGREES {
+            let mut btree = evens(degree, 400);
+            let taken: Vec<i32> = btree.extract_if(|key, _| key % 4 == 0).map(|(key, _)| key).take(10).collect();
+            assert_eq!(taken, (0..10).map(|i| i * 4).collect::<Vec<_>>());
+            btree.validate().unwrap();
+            assert_eq!(btree.len(), 190);
+            assert!(btree.keys().copied().eq((0..200).map(|i| i * 2).filter(|key| *key >= 40 || key % 4 != 0)));
+        }
+    }
+}
@@ -0,0 +1,1484 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::mem;
+use std::path::Path;
+
+use crate::codec::Codec;
+use crate::error::{BTreeError, DiskError, Violation};
+use crate::node::{BTreeRules, Item};
+use crate::pager::{Header, Journal, PAGE_SIZE, PageId, Pager};
+use crate::pool::{BufferPool, Eviction, PoolStats};
+use crate::shadow::Snapshot;
+use crate::wal::Durability;
+
+// A node's page: a kind byte and a `u16` item count, then (internal nodes only) the page id of
+// every child, then the items, each key followed by its value.
+const LEAF: u8 = 0;
+const INTERNAL: u8 = 1;
+pub(crate) const NODE_HEADER: usize = 3;
+
+// the most bytes one encoded entry may take, so that a full node still fits in its page
+fn entry_budget(rules: &BTreeRules) -> usize {
+    let children = rules.maxchildren * size_of::<PageId>();
+    PAGE_SIZE.saturating_sub(NODE_HEADER + children) / rules.maxkeys
+}
+
+// Nodes are sized one of two ways. By count, from a degree, as in memory: every entry gets an
+// equal share of the page. Or by bytes, for entries that vary in length, each taking up to
+// `SLACK` with its child id. A node that isn't full has room for three more of those: on the
+// way down a delete may split two of its children and then swap a bigger entry into it. Two
+// nodes that can't spare an entry always merge, separator and all, into one that isn't full.
+const SLACK: usize = PAGE_SIZE / 10;
+const SPLIT_OVER: usize = PAGE_SIZE - 3 * SLACK;
+const SPARE_FROM: usize = (PAGE_SIZE - 4 * SLACK) / 2;
+
+#[derive(Debug, Clone, Copy)]
+enum Fit {
+    Count(BTreeRules),
+    Bytes,
+}
+
+impl Fit {
+    fn max_entry(&self) -> usize {
+        match self {
+            Fit::Count(rules) => entry_budget(rules),
+            Fit::Bytes => SLACK - size_of::<PageId>(),
+        }
+    }
+    // split on the way down, so there's room for whatever comes up from below
+    fn full<K: Codec, V: Codec>(&self, node: &PageNode<K, V>) -> bool {
+        match self {
+            Fit::Count(rules) => node.items.len() >= rules.maxkeys,
+            Fit::Bytes => node.size() > SPLIT_OVER,
+        }
+    }
+    // can lose an entry on the way down
+    fn enough<K: Codec, V: Codec>(&self, node: &PageNode<K, V>) -> bool {
+        match self {
+            Fit::Count(rules) => node.items.len() >= rules.degree,
+            Fit::Bytes => node.size() >= SPARE_FROM,
+        }
+    }
+    // sized by bytes, a rotation can grow a node by most of an entry, so deletes split full
+    // nodes on the way down too
+    fn crowded<K: Codec, V: Codec>(&self, node: &PageNode<K, V>) -> bool {
+        matches!(self, Fit::Bytes) && self.full(node)
+    }
+    fn median<K: Codec, V: Codec>(&self, node: &PageNode<K, V>) -> usize {
+        match self {
+            Fit::Count(_) => node.items.len() / 2,
+            Fit::Bytes => node.byte_median(),
+        }
+    }
+    // what the file's header records: 0 stands for sized by bytes
+    fn degree(&self) -> usize {
+        match self {
+            Fit::Count(rules) => rules.degree,
+            Fit::Bytes => 0,
+        }
+    }
+}
+
+// a node read out of its page, a leaf when it has no children
+#[derive(Debug)]
+pub(crate) struct PageNode<K, V> {
+    pub(crate) items: Vec<Item<K, V>>,
+    pub(crate) children: Vec<PageId>,
+}
+
+impl<K: Codec, V: Codec> PageNode<K, V> {
+    fn leaf() -> Self {
+        PageNode { items: Vec::new(), children: Vec::new() }
+    }
+    pub(crate) fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+    fn size(&self) -> usize {
+        self.encode().len()
+    }
+    fn byte_median(&self) -> usize {
+        // the item the bytes balance on, kept off both ends so neither half comes out empty
+        let sizes: Vec<usize> = self.items.iter().map(entry_size).collect();
+        let half = sizes.iter().sum::<usize>() / 2;
+        let mut before = 0;
+        let median = sizes.iter().position(|size| {
+            before += size;
+            before > half
+        });
+        median.unwrap_or(0).clamp(1, self.items.len() - 2)
+    }
+    fn split(&mut self, median: usize) -> (Item<K, V>, PageNode<K, V>) {
+        // -- the median goes up to the parent, each side keeps one more child than items
+        let items = self.items.split_off(median + 1);
+        let median_item = self.items.pop().unwrap();
+        let children = if self.is_leaf() { Vec::new() } else { self.children.split_off(median + 1) };
+        (median_item, PageNode { items, children })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut page = Vec::with_capacity(PAGE_SIZE);
+        page.push(if self.is_leaf() { LEAF } else { INTERNAL });
+        (self.items.len() as u16).encode(&mut page);
+        for child in &self.children {
+            child.encode(&mut page);
+        }
+        for item in &self.items {
+            item.key.encode(&mut page);
+            item.value.encode(&mut page);
+        }
+        page
+    }
+    pub(crate) fn decode(id: PageId, mut page: &[u8]) -> Result<Self, DiskError> {
+        let corrupt = |reason| DiskError::Corrupt { page: id, reason };
+        let (kind, count) = match (u8::decode(&mut page), u16::decode(&mut page)) {
+            (Some(kind), Some(count)) => (kind, count as usize),
+            _ => return Err(corrupt("node header is cut short")),
+        };
+        let children = match kind {
+            LEAF => Vec::new(),
+            INTERNAL => (0..=count)
+                .map(|_| PageId::decode(&mut page))
+                .collect::<Option<Vec<_>>>()
+                .ok_or(corrupt("child ids are cut short"))?,
+            _ => return Err(corrupt("unknown node kind")),
+        };
+        let mut items = Vec::with_capacity(count);
+        for _ in 0..count {
+            match (K::decode(&mut page), V::decode(&mut page)) {
+                (Some(key), Some(value)) => items.push(Item { key, value }),
+                _ => return Err(corrupt("an entry doesn't decode")),
+            }
+        }
+        Ok(PageNode { items, children })
+    }
+}
+
+// a node split in two, left and right of the median
+type Halves<K, V> = (PageNode<K, V>, PageNode<K, V>);
+
+fn entry_size<K: Codec, V: Codec>(item: &Item<K, V>) -> usize {
+    let mut bytes = Vec::new();
+    item.key.encode(&mut bytes);
+    item.value.encode(&mut bytes);
+    bytes.len()
+}
+
+pub(crate) fn search<K, V, Q>(items: &[Item<K, V>], key: &Q) -> Result<usize, usize>
+where
+    K: Borrow<Q>,
+    Q: Ord + ?Sized,
+{
+    items.binary_search_by(|item| item.key.borrow().cmp(key))
+}
+
+/// A sorted map like [`BTree`](crate::BTree), but kept in a file of
+/// fixed-size pages rather than in memory, so it can hold more than fits in
+/// RAM and picks up where it left off when the file is reopened.
+///
+/// Every node lives in a 4 KiB page of its own and refers to its children by
+/// page id. Operations make the same single proactive pass down the tree as
+/// the in-memory one, reading only the pages on their path and writing back
+/// the ones they change. Keys and values go into pages through their
+/// [`Codec`] implementations.
+///
+/// Nodes are sized either by a degree, where each entry has to fit in its
+/// share of a full node's page, or, with [`create_for_page`](Self::create_for_page)
+/// and variable-length keys or values, by how many bytes they take up.
+///
+/// Pages are read and written through a buffer pool (see
+/// [`set_pool`](Self::set_pool)) and changed pages go to a write-ahead log
+/// beside the file, at its path with `-wal` on the end. Each insert or
+/// remove commits to the log as a whole, as durably as
+/// [`set_durability`](Self::set_durability) asks, and the log is copied
+/// into the file as it grows, on [`flush`](Self::flush) and when the tree
+/// is dropped. Opening a tree redoes whatever the log committed that the
+/// file is missing, so a crash never leaves part of a change behind. An
+/// insert or remove that fails part way is undone before it returns, and
+/// the tree carries on from its last commit.
+/// Trees made with [`create_copy_on_write`](Self::create_copy_on_write)
+/// keep no log, and never overwrite a page the last commit reaches.
+///
+/// ```no_run
+/// use btree::DiskBTree;
+///
+/// let mut tree = DiskBTree::create("inventory.db", 8)?;
+/// tree.insert(23, String::from("Nerevar's Ring"))?;
+/// drop(tree);
+///
+/// let mut tree = DiskBTree::<i32, String>::open("inventory.db")?;
+/// assert_eq!(tree.get(&23)?.as_deref(), Some("Nerevar's Ring"));
+/// # Ok::<(), btree::DiskError>(())
+/// ```
+#[derive(Debug)]
+pub struct DiskBTree<K, V> {
+    pager: Pager,
+    fit: Fit,
+    entries: PhantomData<(K, V)>,
+}
+
+impl<K, V> DiskBTree<K, V>
+where
+    K: Ord + Codec,
+    V: Codec,
+{
+    /// Creates an empty tree of minimum degree `degree` in a new file at
+    /// `path`, replacing anything already there.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `degree` is less than 2, or so large that a full node's
+    /// child ids leave no room in its page for entries.
+    pub fn create(path: impl AsRef<Path>, degree: usize) -> Result<Self, DiskError> {
+        let rules = BTreeRules::new(degree);
+        assert!(entry_budget(&rules) > 0, "a degree of {degree} leaves no room for entries in a {PAGE_SIZE}-byte page");
+        Self::create_with(path.as_ref(), Fit::Count(rules), false)
+    }
+
+    /// Creates an empty copy-on-write tree of minimum degree `degree` in a
+    /// new file at `path`, replacing anything already there.
+    ///
+    /// A copy-on-write tree keeps no log. Changes never overwrite a page the
+    /// last one left the tree reaching, but copy every page they change,
+    /// along with the path down to it, and then commit by writing the new
+    /// root's id into whichever of the file's two headers is older. A crash
+    /// before that leaves the other header, and the tree it reaches,
+    /// untouched. The pages a change copies away from are reused once
+    /// nothing needs them, which leaves room for
+    /// [`snapshot`](Self::snapshot)s of the tree as it stood.
+    ///
+    /// # Panics
+    ///
+    /// As for [`create`](Self::create).
+    pub fn create_copy_on_write(path: impl AsRef<Path>, degree: usize) -> Result<Self, DiskError> {
+        let rules = BTreeRules::new(degree);
+        assert!(entry_budget(&rules) > 0, "a degree of {degree} leaves no room for entries in a {PAGE_SIZE}-byte page");
+        Self::create_with(path.as_ref(), Fit::Count(rules), true)
+    }
+
+    /// Creates an empty tree in a new file at `path`, replacing anything
+    /// already there, with nodes sized to the page instead of to a degree.
+    ///
+    /// If `K` and `V` both encode to a fixed [`WIDTH`](Codec::WIDTH), the
+    /// degree is the largest whose full node still fits in a page, as
+    /// [`BTreeRules::for_node_size`] works it out. Otherwise nodes are sized
+    /// by bytes: one splits once it fills about 70% of its page and merges
+    /// once it's down to about 30%, so short and long entries (up to a tenth
+    /// of a page each) share pages as they fit.
+    pub fn create_for_page(path: impl AsRef<Path>) -> Result<Self, DiskError> {
+        let fit = match (K::WIDTH, V::WIDTH) {
+            (Some(_), Some(_)) => Fit::Count(BTreeRules::for_node_size::<K, V>(PAGE_SIZE)),
+            _ => Fit::Bytes,
+        };
+        Self::create_with(path.as_ref(), fit, false)
+    }
+
+    fn create_with(path: &Path, fit: Fit, copy_on_write: bool) -> Result<Self, DiskError> {
+        let pager = Pager::create(path, fit.degree(), copy_on_write)?;
+        let mut tree = DiskBTree { pager, fit, entries: PhantomData };
+        let root = tree.pager.allocate()?;
+        tree.pager.header.root = root;
+        tree.store(root, &PageNode::leaf())?;
+        tree.pager.commit()?;
+        // the file gets a header straight away, so it opens even if nothing else ever reaches it
+        tree.pager.flush()?;
+        Ok(tree)
+    }
+
+    /// Opens a tree that one of the `create` functions made earlier, sizing
+    /// its nodes and keeping it whole the way it was created to.
+    ///
+    /// The file doesn't record the key and value types, so they have to be
+    /// the ones it was written with. A copy-on-write tree reads all its
+    /// internal nodes to find which pages it's free to reuse.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DiskError> {
+        let pager = Pager::open(path.as_ref())?;
+        let fit = match pager.header.degree {
+            0 => Fit::Bytes,
+            degree => Fit::Count(BTreeRules::new(degree)),
+        };
+        let mut tree = DiskBTree { pager, fit, entries: PhantomData };
+        if tree.pager.header.copy_on_write {
+            let reached = tree.reachable()?;
+            tree.pager.reclaim(&reached);
+        }
+        Ok(tree)
+    }
+
+    /// The minimum degree this tree was created with, or `None` if its nodes
+    /// are sized by bytes.
+    pub fn degree(&self) -> Option<usize> {
+        match self.fit {
+            Fit::Count(rules) => Some(rules.degree),
+            Fit::Bytes => None,
+        }
+    }
+
+    /// Number of key-value pairs in the tree.
+    pub fn len(&self) -> usize {
+        self.pager.header.len
+    }
+
+    /// `true` if the tree holds no key-value pairs.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads the value stored under `key`, if any.
+    pub fn get<Q>(&mut self, key: &Q) -> Result<Option<V>, DiskError>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::trace_span!("disk_btree.get").entered();
+        let mut node = self.load(self.pager.header.root)?;
+        loop {
+            match search(&node.items, key) {
+                Ok(position) => return Ok(Some(node.items.swap_remove(position).value)),
+                Err(_) if node.is_leaf() => return Ok(None),
+                Err(position) => node = self.load(node.children[position])?,
+            }
+        }
+    }
+
+    /// `true` if `key` is in the tree.
+    pub fn contains_key<Q>(&mut self, key: &Q) -> Result<bool, DiskError>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Inserts `value` under `key`, handing back the value it replaced, if
+    /// any. The stored key itself is left untouched.
+    ///
+    /// Fails with [`DiskError::EntryTooBig`], before anything is written, if
+    /// the encoded entry won't fit in its share of a page.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, DiskError> {
+        let _span = tracing::debug_span!("disk_btree.insert", len = self.len()).entered();
+        let mut bytes = Vec::new();
+        key.encode(&mut bytes);
+        value.encode(&mut bytes);
+        let max = self.fit.max_entry();
+        if bytes.len() > max {
+            return Err(DiskError::EntryTooBig { bytes: bytes.len(), max });
+        }
+        let put = self.put(Item { key, value });
+        self.or_roll_back(put)
+    }
+
+    fn put(&mut self, item: Item<K, V>) -> Result<Option<V>, DiskError> {
+        let (mut id, mut node) = self.load_root()?;
+        if self.fit.full(&node) {
+            (id, node) = self.split_root(id, node)?;
+        }
+
+        // the same proactive pass as `Node::insert`: split full children on the way down, so
+        // the leaf we land in always has room
+        let mut depth = 0;
+        let old = loop {
+            let position = match search(&node.items, &item.key) {
+                Ok(position) => {
+                    let old = mem::replace(&mut node.items[position].value, item.value);
+                    self.store(id, &node)?;
+                    break Some(old);
+                }
+                Err(position) if node.is_leaf() => {
+                    node.items.insert(position, item);
+                    self.store(id, &node)?;
+                    self.pager.header.len += 1;
+                    break None;
+                }
+                Err(position) => position,
+            };
+            let mut child = self.load_child(id, &mut node, position)?;
+            let mut child_id = node.children[position];
+            if self.fit.full(&child) {
+                let (left, right) = self.split_child(id, &mut node, position, child, depth)?;
+
+                // the median may have overtaken us, or be the very key we're inserting
+                match item.key.cmp(&node.items[position].key) {
+                    Ordering::Greater => (child_id, child) = (node.children[position + 1], right),
+                    Ordering::Equal => {
+                        let old = mem::replace(&mut node.items[position].value, item.value);
+                        self.store(id, &node)?;
+                        break Some(old);
+                    }
+                    Ordering::Less => child = left,
+                }
+            }
+            (id, node) = (child_id, child);
+            depth += 1;
+        };
+        self.pager.commit()?;
+        Ok(old)
+    }
+
+    /// Removes `key`, handing back its value if it was there. A key that
+    /// isn't there leaves the file untouched.
+    pub fn remove<Q>(&mut self, key: &Q) -> Result<Option<V>, DiskError>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::debug_span!("disk_btree.remove", len = self.len()).entered();
+        // the descent rebalances on its way down whether or not the key's there, so it's looked
+        // up first: nothing to remove, nothing to copy or commit
+        if !self.contains_key(key)? {
+            return Ok(None);
+        }
+        let take = self.take(key);
+        self.or_roll_back(take)
+    }
+
+    fn take<Q>(&mut self, key: &Q) -> Result<Option<V>, DiskError>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (mut root, mut node) = self.load_root()?;
+        if self.fit.crowded(&node) {
+            (root, node) = self.split_root(root, node)?;
+        }
+        let removed = self.delete(root, node, key, 0)?;
+        if removed.is_some() {
+            self.pager.header.len -= 1;
+        }
+
+        // a merge under the root may have pulled its last key down
+        let node = self.load(root)?;
+        if node.items.is_empty() && !node.is_leaf() {
+            self.pager.header.root = node.children[0];
+            self.pager.free(root)?;
+            tracing::debug!(root = self.pager.header.root, "root emptied, tree shrank a level");
+        }
+        self.pager.commit()?;
+        Ok(removed.map(|item| item.value))
+    }
+
+    /// Replaces the buffer pool with one of `frames` pages that evicts by
+    /// `eviction`, writing back the pages the old one had changed. Trees
+    /// start out with 64 frames and [`Eviction::Lru`].
+    ///
+    /// # Panics
+    ///
+    /// If `frames` is 0.
+    pub fn set_pool(&mut self, frames: usize, eviction: Eviction) -> Result<(), DiskError> {
+        self.pager.replace_pool(BufferPool::new(frames, eviction))
+    }
+
+    /// How many page reads the buffer pool has served from memory and how
+    /// many went to the file, since the tree was opened or its pool last
+    /// replaced.
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pager.pool.stats
+    }
+
+    /// Sets how far each insert or remove waits for its change to reach the
+    /// disk before returning. Trees start out with
+    /// [`Durability::EveryCommit`].
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.pager.durability = durability;
+    }
+
+    /// Copies everything committed to the log into the file, empties the log
+    /// and waits for the file to reach the disk. Copy-on-write, there's only
+    /// the waiting.
+    pub fn flush(&mut self) -> Result<(), DiskError> {
+        let _span = tracing::debug_span!("disk_btree.flush", len = self.len()).entered();
+        self.pager.flush()
+    }
+
+    /// A read-only view of the tree as it stands, which later changes to it
+    /// leave alone, or `None` if it wasn't created
+    /// [copy-on-write](Self::create_copy_on_write).
+    pub fn snapshot(&self) -> Result<Option<Snapshot<K, V>>, DiskError> {
+        let Journal::Shadow(shadows) = &self.pager.journal else { return Ok(None) };
+        let Header { root, len, txn, .. } = self.pager.header;
+        Ok(Some(shadows.snapshot(self.pager.reader()?, root, len, txn)))
+    }
+
+    /// Reads the whole tree checking the same invariants as
+    /// [`BTree::validate`](crate::BTree::validate), reporting the first
+    /// violation found as a [`DiskError::Tree`].
+    pub fn validate(&mut self) -> Result<(), DiskError> {
+        let _span = tracing::debug_span!("disk_btree.validate", len = self.len()).entered();
+        let actual = self.check(self.pager.header.root, (None, None), 0, &mut None, &mut Vec::new())?;
+        if self.len() != actual {
+            let violation = Violation::CounterDrift { counter: "len", cached: self.len(), actual };
+            return Err(BTreeError::Invalid { path: Vec::new(), violation }.into());
+        }
+        Ok(())
+    }
+
+    // A change that fails part way is undone, so the tree carries on from its last commit rather
+    // than from whatever the change left half done. The change's own error is the one handed back.
+    fn or_roll_back<T>(&mut self, result: Result<T, DiskError>) -> Result<T, DiskError> {
+        if result.is_err()
+            && let Err(error) = self.pager.roll_back()
+        {
+            tracing::warn!(%error, "failed to roll back a failed change");
+        }
+        result
+    }
+
+    fn load(&mut self, id: PageId) -> Result<PageNode<K, V>, DiskError> {
+        self.pager.read(id, |page| PageNode::decode(id, page))?
+    }
+    fn store(&mut self, id: PageId, node: &PageNode<K, V>) -> Result<(), DiskError> {
+        self.pager.write(id, &node.encode())
+    }
+
+    // Reads in the root or one of `node`'s children, to change. Copy-on-write, a page the last
+    // commit reaches is copied to a fresh one first and whatever pointed at it repointed, `node`
+    // being fresh itself by then. Everything from here down then changes copies in place. Pages
+    // that may be left as they are, like a sibling that may not have a key to spare, are read
+    // with `load` and only claimed once it's certain they'll change.
+    fn load_root(&mut self) -> Result<(PageId, PageNode<K, V>), DiskError> {
+        let id = self.pager.header.root;
+        let node = self.load(id)?;
+        let Some(copy) = self.pager.shadow(id)? else { return Ok((id, node)) };
+        self.store(copy, &node)?;
+        self.pager.header.root = copy;
+        Ok((copy, node))
+    }
+    fn load_child(&mut self, id: PageId, node: &mut PageNode<K, V>, position: usize) -> Result<PageNode<K, V>, DiskError> {
+        let child = self.load(node.children[position])?;
+        self.claim(id, node, position, &child)?;
+        Ok(child)
+    }
+    fn claim(&mut self, id: PageId, node: &mut PageNode<K, V>, position: usize, child: &PageNode<K, V>) -> Result<(), DiskError> {
+        if let Some(copy) = self.pager.shadow(node.children[position])? {
+            self.store(copy, child)?;
+            node.children[position] = copy;
+            self.store(id, node)?;
+        }
+        Ok(())
+    }
+
+    fn split_root(&mut self, id: PageId, mut node: PageNode<K, V>) -> Result<(PageId, PageNode<K, V>), DiskError> {
+        // the old root keeps its page as the left half, under a new root page
+        let (median, right) = node.split(self.fit.median(&node));
+        let right_id = self.pager.allocate()?;
+        self.store(id, &node)?;
+        self.store(right_id, &right)?;
+        let root = self.pager.allocate()?;
+        let node = PageNode { items: vec![median], children: vec![id, right_id] };
+        self.store(root, &node)?;
+        self.pager.header.root = root;
+        tracing::debug!(root, left = id, right = right_id, "root split, tree grew a level");
+        Ok((root, node))
+    }
+    fn split_child(
+        &mut self,
+        id: PageId,
+        node: &mut PageNode<K, V>,
+        position: usize,
+        mut child: PageNode<K, V>,
+        depth: usize,
+    ) -> Result<Halves<K, V>, DiskError> {
+        // hands back both halves, the median between them having gone up into `node`
+        let (median, right) = child.split(self.fit.median(&child));
+        let right_id = self.pager.allocate()?;
+        self.store(node.children[position], &child)?;
+        self.store(right_id, &right)?;
+        node.items.insert(position, median);
+        node.children.insert(position + 1, right_id);
+        self.store(id, node)?;
+        tracing::debug!(depth, position, page = id, "split full child");
+        Ok((child, right))
+    }
+    fn split_crowded(&mut self, id: PageId, node: &mut PageNode<K, V>, position: usize, depth: usize) -> Result<bool, DiskError> {
+        if let Fit::Count(_) = self.fit {
+            return Ok(false);
+        }
+        let child = self.load(node.children[position])?;
+        if !self.fit.crowded(&child) {
+            return Ok(false);
+        }
+        self.claim(id, node, position, &child)?;
+        self.split_child(id, node, position, child, depth)?;
+        Ok(true)
+    }
+
+    // the same descent as `Node::delete`, with each node read in on the way down and written
+    // back wherever it changes
+    fn delete<Q>(&mut self, id: PageId, mut node: PageNode<K, V>, key: &Q, depth: usize) -> Result<Option<Item<K, V>>, DiskError>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let found = search(&node.items, key);
+        if node.is_leaf() {
+            let Ok(position) = found else { return Ok(None) };
+            let item = node.items.remove(position);
+            self.store(id, &node)?;
+            return Ok(Some(item));
+        }
+        // the children we're about to touch get split first if they're crowded, and then we
+        // start over here with the median in among our items
+        let nearby = match found {
+            Ok(position) => position..position + 2,
+            Err(position) => position..position + 1,
+        };
+        for position in nearby {
+            if self.split_crowded(id, &mut node, position, depth)? {
+                return self.delete(id, node, key, depth);
+            }
+        }
+        let position = match found {
+            Ok(position) => {
+                // we hold the key: swap in a neighbour from whichever side can spare one, or fold
+                // both sides together and chase the key down into them
+                let left = self.load(node.children[position])?;
+                if self.fit.enough(&left) {
+                    self.claim(id, &mut node, position, &left)?;
+                    let predecessor = self.delete_max(node.children[position], left, depth + 1)?;
+                    let removed = mem::replace(&mut node.items[position], predecessor);
+                    self.store(id, &node)?;
+                    return Ok(Some(removed));
+                }
+                let right = self.load(node.children[position + 1])?;
+                if self.fit.enough(&right) {
+                    self.claim(id, &mut node, position + 1, &right)?;
+                    let successor = self.delete_min(node.children[position + 1], right, depth + 1)?;
+                    let removed = mem::replace(&mut node.items[position], successor);
+                    self.store(id, &node)?;
+                    return Ok(Some(removed));
+                }
+                self.claim(id, &mut node, position, &left)?;
+                let merged = self.merge(id, &mut node, position, left, right, depth)?;
+                return self.delete(node.children[position], merged, key, depth + 1);
+            }
+            Err(position) => position,
+        };
+        let (position, child) = self.make_enough(id, &mut node, position, depth)?;
+        self.delete(node.children[position], child, key, depth + 1)
+    }
+    fn delete_max(&mut self, id: PageId, mut node: PageNode<K, V>, depth: usize) -> Result<Item<K, V>, DiskError> {
+        if node.is_leaf() {
+            let item = node.items.pop().unwrap();
+            self.store(id, &node)?;
+            return Ok(item);
+        }
+        let last = node.children.len() - 1;
+        if self.split_crowded(id, &mut node, last, depth)? {
+            return self.delete_max(id, node, depth);
+        }
+        let (position, child) = self.make_enough(id, &mut node, last, depth)?;
+        self.delete_max(node.children[position], child, depth + 1)
+    }
+    fn delete_min(&mut self, id: PageId, mut node: PageNode<K, V>, depth: usize) -> Result<Item<K, V>, DiskError> {
+        if node.is_leaf() {
+            let item = node.items.remove(0);
+            self.store(id, &node)?;
+            return Ok(item);
+        }
+        if self.split_crowded(id, &mut node, 0, depth)? {
+            return self.delete_min(id, node, depth);
+        }
+        let (position, child) = self.make_enough(id, &mut node, 0, depth)?;
+        self.delete_min(node.children[position], child, depth + 1)
+    }
+
+    fn make_enough(
+        &mut self,
+        id: PageId,
+        node: &mut PageNode<K, V>,
+        position: usize,
+        depth: usize,
+    ) -> Result<(usize, PageNode<K, V>), DiskError> {
+        // hands back the child to descend into, read in and with enough keys to lose one, and its
+        // position, which moves left if it merged into its left sibling
+        let mut child = self.load_child(id, node, position)?;
+        if self.fit.enough(&child) {
+            return Ok((position, child));
+        }
+
+        // rotating a key over beats a merge, so ask the right neighbour, then the left
+        let right = match position + 1 < node.children.len() {
+            true => Some(self.load(node.children[position + 1])?),
+            false => None,
+        };
+        let right = match right {
+            Some(mut right) if self.fit.enough(&right) => {
+                self.claim(id, node, position + 1, &right)?;
+                let item = right.items.remove(0);
+                child.items.push(mem::replace(&mut node.items[position], item));
+                if !right.is_leaf() {
+                    child.children.push(right.children.remove(0));
+                }
+                self.store(node.children[position + 1], &right)?;
+                self.store(node.children[position], &child)?;
+                self.store(id, node)?;
+                tracing::debug!(depth, position, sibling = position + 1, page = id, "rotated key from sibling");
+                return Ok((position, child));
+            }
+            right => right,
+        };
+        let left = match position.checked_sub(1) {
+            Some(sibling) => Some(self.load(node.children[sibling])?),
+            None => None,
+        };
+        let left = match left {
+            Some(mut left) if self.fit.enough(&left) => {
+                self.claim(id, node, position - 1, &left)?;
+                let item = left.items.pop().unwrap();
+                child.items.insert(0, mem::replace(&mut node.items[position - 1], item));
+                if !left.is_leaf() {
+                    child.children.insert(0, left.children.pop().unwrap());
+                }
+                self.store(node.children[position - 1], &left)?;
+                self.store(node.children[position], &child)?;
+                self.store(id, node)?;
+                tracing::debug!(depth, position, sibling = position - 1, page = id, "rotated key from sibling");
+                return Ok((position, child));
+            }
+            left => left,
+        };
+
+        // nobody can spare a key. prefer the right neighbour, the last child only has a left. the
+        // right page of the two goes free, so only a left sibling needs claiming
+        match (left, right) {
+            (_, Some(right)) => Ok((position, self.merge(id, node, position, child, right, depth)?)),
+            (Some(left), None) => {
+                self.claim(id, node, position - 1, &left)?;
+                Ok((position - 1, self.merge(id, node, position - 1, left, child, depth)?))
+            }
+            (None, None) => unreachable!("an internal node has at least two children"),
+        }
+    }
+    fn merge(
+        &mut self,
+        id: PageId,
+        node: &mut PageNode<K, V>,
+        left: usize,
+        mut merged: PageNode<K, V>,
+        right: PageNode<K, V>,
+        depth: usize,
+    ) -> Result<PageNode<K, V>, DiskError> {
+        // -- left ++ separator ++ right into the left page, and the right page goes free
+        merged.items.push(node.items.remove(left));
+        merged.items.extend(right.items);
+        merged.children.extend(right.children);
+        let right_id = node.children.remove(left + 1);
+        self.store(node.children[left], &merged)?;
+        self.store(id, node)?;
+        self.pager.free(right_id)?;
+        tracing::debug!(depth, position = left, page = id, parent_items = node.items.len(), "merged siblings");
+        Ok(merged)
+    }
+
+    // every page the tree reaches, reading only the internal ones: leaves are all at the same
+    // depth, so a level that starts with a leaf is all leaves
+    fn reachable(&mut self) -> Result<HashSet<PageId>, DiskError> {
+        let mut level = vec![self.pager.header.root];
+        let mut reached: HashSet<PageId> = level.iter().copied().collect();
+        while !self.load(level[0])?.is_leaf() {
+            let mut below = Vec::new();
+            for id in level {
+                below.extend(self.load(id)?.children);
+            }
+            reached.extend(&below);
+            level = below;
+        }
+        Ok(reached)
+    }
+
+    fn check(
+        &mut self,
+        id: PageId,
+        bounds: (Option<&K>, Option<&K>),
+        depth: usize,
+        leaf_depth: &mut Option<usize>,
+        path: &mut Vec<usize>,
+    ) -> Result<usize, DiskError> {
+        // `Node::validate` over pages: this node, then its children left to right, handing back
+        // how many items the subtree holds
+        let invalid = |violation| Err(BTreeError::Invalid { path: path.clone(), violation }.into());
+        let node = self.load(id)?;
+        let items = &node.items;
+
+        match self.fit {
+            Fit::Count(rules) => {
+                if items.len() > rules.maxkeys {
+                    return invalid(Violation::TooManyKeys { keys: items.len(), max: rules.maxkeys });
+                }
+                if depth > 0 && items.len() < rules.minkeys {
+                    return invalid(Violation::TooFewKeys { keys: items.len(), min: rules.minkeys });
+                }
+            }
+            // sized by bytes, how full a node runs depends on what's in it, so all that's certain
+            // is that it fits its page and, below the root, isn't empty
+            Fit::Bytes => {
+                let bytes = node.size();
+                if bytes > PAGE_SIZE {
+                    return invalid(Violation::TooManyBytes { bytes, max: PAGE_SIZE });
+                }
+                if depth > 0 && items.is_empty() {
+                    return invalid(Violation::TooFewKeys { keys: 0, min: 1 });
+                }
+            }
+        }
+        for index in 1..items.len() {
+            if items[index - 1].key >= items[index].key {
+                return invalid(Violation::UnsortedKeys { index });
+            }
+        }
+        let (lower, upper) = bounds;
+        for (index, item) in items.iter().enumerate() {
+            if lower.is_some_and(|lower| item.key <= *lower) || upper.is_some_and(|upper| item.key >= *upper) {
+                return invalid(Violation::KeyOutOfBounds { index });
+            }
+        }
+        if node.is_leaf() {
+            let expected = *leaf_depth.get_or_insert(depth);
+            if depth != expected {
+                return invalid(Violation::UnevenLeaves { depth, expected });
+            }
+            return Ok(items.len());
+        }
+
+        let mut size = items.len();
+        for (position, &child) in node.children.iter().enumerate() {
+            let lower = position.checked_sub(1).map(|index| &items[index].key).or(lower);
+            let upper = items.get(position).map(|item| &item.key).or(upper);
+            path.push(position);
+            size += self.check(child, (lower, upper), depth + 1, leaf_depth, path)?;
+            path.pop();
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DEGREES;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::{env, fs, mem, process};
+
+    use crate::pager::{HEADER_SIZE, first_node};
+    use crate::wal::wal_path;
+
+    // the shared degrees whose full nodes fit in a page
+    fn page_degrees() -> impl Iterator<Item = usize> {
+        DEGREES.into_iter().filter(|&degree| entry_budget(&BTreeRules::new(degree)) > 0)
+    }
+
+    // a file of our own under the temp dir, gone along with its log when dropped
+    struct Scratch(PathBuf);
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            Scratch(env::temp_dir().join(format!("btree-{}-{name}.db", process::id())))
+        }
+    }
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+            let _ = fs::remove_file(wal_path(&self.0));
+        }
+    }
+
+    #[test]
+    fn matches_std_through_inserts_and_removes() {
+        for degree in page_degrees() {
+            let file = Scratch::new(&format!("matches-{degree}"));
+            let mut tree = DiskBTree::create(&file.0, degree).unwrap();
+            let mut expected = BTreeMap::new();
+            for key in (0..3000).map(|i| (i * 37) % 3000) {
+                assert_eq!(tree.insert(key, key * 2).unwrap(), expected.insert(key, key * 2));
+            }
+            assert_eq!(tree.insert(1500, -1).unwrap(), expected.insert(1500, -1));
+            tree.validate().unwrap();
+
+            for key in (0..3000).map(|i| (i * 53) % 3000).filter(|key| key % 3 != 0) {
+                assert_eq!(tree.remove(&key).unwrap(), expected.remove(&key));
+            }
+            assert_eq!(tree.remove(&1).unwrap(), None);
+            tree.validate().unwrap();
+            assert_eq!(tree.len(), expected.len());
+            for key in -1..3001 {
+                assert_eq!(tree.get(&key).unwrap(), expected.get(&key).copied(), "key {key} at degree {degree}");
+            }
+        }
+    }
+
+    #[test]
+    fn survives_reopening() {
+        let file = Scratch::new("reopen");
+        let mut tree = DiskBTree::create(&file.0, 8).unwrap();
+        for key in 0..5000u64 {
+            tree.insert(key, format!("item {key}")).unwrap();
+        }
+        tree.flush().unwrap();
+        drop(tree);
+
+        let mut tree = DiskBTree::<u64, String>::open(&file.0).unwrap();
+        assert_eq!((tree.len(), tree.degree()), (5000, Some(8)));
+        tree.validate().unwrap();
+        for key in (0..5000).step_by(2) {
+            assert_eq!(tree.remove(&key).unwrap(), Some(format!("item {key}")));
+        }
+        drop(tree);
+
+        let mut tree = DiskBTree::<u64, String>::open(&file.0).unwrap();
+        tree.validate().unwrap();
+        assert_eq!(tree.len(), 2500);
+        assert_eq!(tree.get(&4001).unwrap().as_deref(), Some("item 4001"));
+        assert_eq!(tree.get(&4000).unwrap(), None);
+    }
+
+    #[test]
+    fn freed_pages_are_reused() {
+        let file = Scratch::new("free-list");
+        let mut tree = DiskBTree::create(&file.0, 2).unwrap();
+        for key in 0..2000 {
+            tree.insert(key, ()).unwrap();
+        }
+        let pages = tree.pager.header.pages;
+        for key in 0..2000 {
+            tree.remove(&key).unwrap();
+        }
+        tree.validate().unwrap();
+        assert!(tree.is_empty());
+        for key in 0..2000 {
+            tree.insert(key, ()).unwrap();
+        }
+        tree.validate().unwrap();
+        assert_eq!(tree.pager.header.pages, pages);
+    }
+
+    #[test]
+    fn string_keys_take_str_lookups() {
+        let file = Scratch::new("strings");
+        let mut tree = DiskBTree::create(&file.0, 3).unwrap();
+        for name in ["Vivec", "Almalexia", "Sotha Sil", "Dagoth Ur", "Nerevar"] {
+            tree.insert(name.to_string(), name.len() as u32).unwrap();
+        }
+        assert_eq!(tree.get("Nerevar").unwrap(), Some(7));
+        assert!(tree.contains_key("Sotha Sil").unwrap());
+        assert_eq!(tree.remove("Vivec").unwrap(), Some(5));
+        assert!(!tree.contains_key("Vivec").unwrap());
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn oversized_entries_are_refused() {
+        let file = Scratch::new("oversized");
+        let mut tree = DiskBTree::create(&file.0, 64).unwrap();
+        let max = tree.fit.max_entry();
+        let error = tree.insert(1u32, "x".repeat(max)).unwrap_err();
+        assert!(matches!(error, DiskError::EntryTooBig { bytes, .. } if bytes == max + 8));
+        assert!(tree.is_empty());
+        // exactly the budget still fits
+        tree.insert(1u32, "x".repeat(max - 8)).unwrap();
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn fixed_width_entries_get_a_page_sized_degree() {
+        let file = Scratch::new("page-degree");
+        let mut tree = DiskBTree::create_for_page(&file.0).unwrap();
+        assert_eq!(tree.degree(), Some(85));
+        for key in (0..20_000u64).map(|i| (i * 7919) % 20_000) {
+            tree.insert(key, !key).unwrap();
+        }
+        tree.validate().unwrap();
+        // every node below the root holds at least 84 of them, a page each
+        assert!(tree.pager.header.pages < 250, "{} pages", tree.pager.header.pages);
+        assert_eq!(tree.get(&12_345).unwrap(), Some(!12_345));
+    }
+
+    #[test]
+    fn variable_length_entries_are_sized_by_bytes() {
+        let file = Scratch::new("by-bytes");
+        let mut tree = DiskBTree::create_for_page(&file.0).unwrap();
+        assert_eq!(tree.degree(), None);
+        let mut expected = BTreeMap::new();
+        // lengths all over the place, from a few bytes up to most of the budget
+        let entry = |i: usize| (format!("{:0width$}", i, width = 1 + i % 40), vec![i as u8; (i * 31) % 300]);
+        for i in (0..4000).map(|i| (i * 53) % 4000) {
+            let (key, value) = entry(i);
+            assert_eq!(tree.insert(key.clone(), value.clone()).unwrap(), expected.insert(key, value));
+        }
+        tree.validate().unwrap();
+        for i in (0..4000).filter(|i| i % 4 != 0) {
+            let (key, _) = entry(i);
+            assert_eq!(tree.remove(&key).unwrap(), expected.remove(&key));
+        }
+        tree.validate().unwrap();
+        tree.flush().unwrap();
+        drop(tree);
+
+        let mut tree = DiskBTree::<String, Vec<u8>>::open(&file.0).unwrap();
+        assert_eq!((tree.len(), tree.degree()), (expected.len(), None));
+        tree.validate().unwrap();
+        for (key, value) in &expected {
+            assert_eq!(tree.get(key).unwrap().as_ref(), Some(value));
+        }
+        for key in expected.keys() {
+            tree.remove(key).unwrap();
+        }
+        tree.validate().unwrap();
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn oversized_entries_are_refused_by_bytes() {
+        let file = Scratch::new("oversized-bytes");
+        let mut tree = DiskBTree::<String, Vec<u8>>::create_for_page(&file.0).unwrap();
+        let max = tree.fit.max_entry();
+        // the key's and the value's length prefixes count against the budget
+        assert!(matches!(tree.insert(String::new(), vec![0; max - 7]), Err(DiskError::EntryTooBig { .. })));
+        tree.insert(String::new(), vec![0; max - 8]).unwrap();
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn every_eviction_policy_keeps_the_tree_intact() {
+        for eviction in [Eviction::Lru, Eviction::Clock, Eviction::TwoQueue] {
+            let file = Scratch::new(&format!("pool-{eviction:?}"));
+            let mut tree = DiskBTree::create(&file.0, 3).unwrap();
+            // a handful of frames for a tree of hundreds of pages, so evictions never let up
+            tree.set_pool(4, eviction).unwrap();
+            let mut expected = BTreeMap::new();
+            for key in (0..2000u32).map(|i| (i * 37) % 2000) {
+                assert_eq!(tree.insert(key, key).unwrap(), expected.insert(key, key));
+            }
+            for key in (0..2000).step_by(3) {
+                assert_eq!(tree.remove(&key).unwrap(), expected.remove(&key));
+            }
+            let stats = tree.pool_stats();
+            assert!(stats.evictions > 0 && stats.write_backs > 0, "{eviction:?}: {stats:?}");
+            drop(tree);
+
+            let mut tree = DiskBTree::<u32, u32>::open(&file.0).unwrap();
+            tree.validate().unwrap();
+            for key in 0..2000 {
+                assert_eq!(tree.get(&key).unwrap(), expected.get(&key).copied(), "{eviction:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn hot_pages_are_served_from_the_pool() {
+        let file = Scratch::new("pool-hits");
+        let mut tree = DiskBTree::create(&file.0, 8).unwrap();
+        for key in 0..2000u32 {
+            tree.insert(key, key).unwrap();
+        }
+        tree.flush().unwrap();
+        tree.set_pool(16, Eviction::Lru).unwrap();
+        for _ in 0..10 {
+            assert_eq!(tree.get(&1234).unwrap(), Some(1234));
+        }
+        // one miss for each level on the first lookup, hits for the rest
+        let stats = tree.pool_stats();
+        let levels = stats.misses;
+        assert_eq!(stats.hits, 9 * levels);
+        assert!(stats.hit_ratio() > 0.85);
+    }
+
+    #[test]
+    fn a_crash_loses_no_committed_change() {
+        let file = Scratch::new("crash");
+        let mut tree = DiskBTree::create(&file.0, 2).unwrap();
+        // few enough frames that pages get evicted part way through changes
+        tree.set_pool(4, Eviction::Lru).unwrap();
+        for key in 0..500u32 {
+            tree.insert(key, key).unwrap();
+        }
+        for key in (0..500).step_by(2) {
+            tree.remove(&key).unwrap();
+        }
+        // gone without a final checkpoint, leaving the latest changes only in the log
+        mem::forget(tree);
+        assert!(fs::metadata(wal_path(&file.0)).unwrap().len() > 0);
+
+        let mut tree = DiskBTree::<u32, u32>::open(&file.0).unwrap();
+        assert_eq!(fs::metadata(wal_path(&file.0)).unwrap().len(), 0);
+        tree.validate().unwrap();
+        assert_eq!(tree.len(), 250);
+        for key in 0..500 {
+            assert_eq!(tree.get(&key).unwrap(), (key % 2 == 1).then_some(key));
+        }
+    }
+
+    #[test]
+    fn a_torn_commit_is_rolled_back() {
+        let file = Scratch::new("torn");
+        let mut tree = DiskBTree::create(&file.0, 3).unwrap();
+        tree.set_durability(Durability::Never);
+        for key in 0..100u32 {
+            tree.insert(key, key).unwrap();
+        }
+        mem::forget(tree);
+        // the last insert's commit record only half made it
+        let log = fs::OpenOptions::new().write(true).open(wal_path(&file.0)).unwrap();
+        log.set_len(log.metadata().unwrap().len() - 20).unwrap();
+
+        let mut tree = DiskBTree::<u32, u32>::open(&file.0).unwrap();
+        tree.validate().unwrap();
+        assert_eq!(tree.len(), 99);
+        assert_eq!(tree.get(&98).unwrap(), Some(98));
+        assert_eq!(tree.get(&99).unwrap(), None);
+    }
+
+    // Fills a tree until its root is full and has children, then garbles every page under the root
+    // in the file, so the next insert splits the root and fails on its way down. The file's bytes
+    // from before go back in once it has.
+    fn fail_an_insert_after_a_root_split(tree: &mut DiskBTree<u32, u32>, path: &Path) {
+        let mut key = 0;
+        let full_above_leaves = |node: PageNode<u32, u32>| !node.is_leaf() && node.items.len() == 3;
+        while !full_above_leaves(tree.load(tree.pager.header.root).unwrap()) {
+            tree.insert(key, key).unwrap();
+            key += 1;
+        }
+        tree.flush().unwrap();
+        // a small pool, emptied, so the garbled pages are read in and changed ones evicted
+        tree.set_pool(2, Eviction::Lru).unwrap();
+        let bytes = fs::read(path).unwrap();
+        let mut garbled = bytes.clone();
+        let root = tree.pager.header.root;
+        for (id, page) in garbled.chunks_mut(PAGE_SIZE).enumerate() {
+            let id = id as PageId;
+            if id >= first_node(tree.pager.header.copy_on_write) && id != root {
+                page[0] = 0xff;
+            }
+        }
+        fs::write(path, garbled).unwrap();
+
+        let header = tree.pager.header;
+        assert!(matches!(tree.insert(key, key), Err(DiskError::Corrupt { .. })));
+        assert_eq!(tree.pager.header, header);
+        fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn a_failed_insert_leaves_the_file_as_it_was() {
+        for copy_on_write in [false, true] {
+            let file = Scratch::new(&format!("failed-insert-{copy_on_write}"));
+            let mut tree = match copy_on_write {
+                false => DiskBTree::create(&file.0, 2).unwrap(),
+                true => DiskBTree::create_copy_on_write(&file.0, 2).unwrap(),
+            };
+            fail_an_insert_after_a_root_split(&mut tree, &file.0);
+            let len = tree.len();
+            drop(tree);
+
+            let mut tree = DiskBTree::<u32, u32>::open(&file.0).unwrap();
+            tree.validate().unwrap();
+            assert_eq!(tree.len(), len);
+            for key in 0..len as u32 {
+                assert_eq!(tree.get(&key).unwrap(), Some(key));
+            }
+        }
+    }
+
+    #[test]
+    fn a_tree_carries_on_after_a_failed_insert() {
+        for copy_on_write in [false, true] {
+            let file = Scratch::new(&format!("carries-on-{copy_on_write}"));
+            let mut tree = match copy_on_write {
+                false => DiskBTree::create(&file.0, 2).unwrap(),
+                true => DiskBTree::create_copy_on_write(&file.0, 2).unwrap(),
+            };
+            fail_an_insert_after_a_root_split(&mut tree, &file.0);
+            // nothing the failed insert allocated or freed is taken for a page the tree uses
+            let len = tree.len() as u32;
+            for key in len..len + 200 {
+                tree.insert(key, key).unwrap();
+            }
+            for key in (0..len + 200).step_by(3) {
+                assert_eq!(tree.remove(&key).unwrap(), Some(key));
+            }
+            tree.validate().unwrap();
+            drop(tree);
+
+            let mut tree = DiskBTree::<u32, u32>::open(&file.0).unwrap();
+            tree.validate().unwrap();
+            for key in 0..len + 200 {
+                assert_eq!(tree.get(&key).unwrap(), (key % 3 != 0).then_some(key), "key {key}");
+            }
+        }
+    }
+
+    #[test]
+    fn a_torn_file_header_is_redone_from_the_log() {
+        let file = Scratch::new("torn-header");
+        let mut tree = DiskBTree::create(&file.0, 3).unwrap();
+        for key in 0..100u32 {
+            tree.insert(key, key).unwrap();
+        }
+        mem::forget(tree);
+        // a checkpoint that crashed part way through writing the header
+        let mut bytes = fs::read(&file.0).unwrap();
+        bytes[..HEADER_SIZE].fill(0xff);
+        fs::write(&file.0, bytes).unwrap();
+
+        let mut tree = DiskBTree::<u32, u32>::open(&file.0).unwrap();
+        tree.validate().unwrap();
+        assert_eq!(tree.len(), 100);
+        drop(tree);
+
+        // and one with nothing in the file yet, everything still in the log
+        let mut tree = DiskBTree::<u32, u32>::create(&file.0, 3).unwrap();
+        tree.insert(7, 7).unwrap();
+        mem::forget(tree);
+        fs::write(&file.0, []).unwrap();
+
+        let mut tree = DiskBTree::<u32, u32>::open(&file.0).unwrap();
+        assert_eq!(tree.get(&7).unwrap(), Some(7));
+    }
+
+    #[test]
+    fn checkpoints_keep_the_log_short() {
+        let file = Scratch::new("checkpoint");
+        let mut tree = DiskBTree::create(&file.0, 8).unwrap();
+        tree.set_durability(Durability::Group(100));
+        let log = |tree: &DiskBTree<u64, u64>| match &tree.pager.journal {
+            Journal::Log(wal) => wal.bytes(),
+            Journal::Shadow(_) => unreachable!(),
+        };
+        let mut longest = 0;
+        for key in 0..10_000 {
+            tree.insert(key, key).unwrap();
+            longest = longest.max(log(&tree));
+        }
+        assert!(longest < 2 * 1024 * PAGE_SIZE as u64, "the log reached {longest} bytes");
+        tree.flush().unwrap();
+        assert_eq!(log(&tree), 0);
+        assert_eq!(fs::metadata(wal_path(&file.0)).unwrap().len(), 0);
+        assert_eq!(tree.get(&9999).unwrap(), Some(9999));
+    }
+
+    #[test]
+    fn copy_on_write_matches_std() {
+        for degree in page_degrees() {
+            let file = Scratch::new(&format!("cow-{degree}"));
+            let mut tree = DiskBTree::create_copy_on_write(&file.0, degree).unwrap();
+            tree.set_durability(Durability::Never);
+            tree.set_pool(8, Eviction::Lru).unwrap();
+            let mut expected = BTreeMap::new();
+            for key in (0..2000u32).map(|i| (i * 37) % 2000) {
+                assert_eq!(tree.insert(key, key * 2).unwrap(), expected.insert(key, key * 2));
+            }
+            for key in (0..2000).map(|i| (i * 53) % 2000).filter(|key| key % 3 != 0) {
+                assert_eq!(tree.remove(&key).unwrap(), expected.remove(&key));
+            }
+            tree.validate().unwrap();
+            let pages = tree.pager.header.pages;
+
+            // every change copies its path, but the copies land in pages earlier changes let go of
+            for key in 0..2000 {
+                tree.insert(key, key).unwrap();
+                tree.remove(&key).unwrap();
+            }
+            assert!(tree.pager.header.pages <= pages + 8, "{} pages from {pages} at degree {degree}", tree.pager.header.pages);
+            drop(tree);
+
+            let mut tree = DiskBTree::<u32, u32>::open(&file.0).unwrap();
+            tree.validate().unwrap();
+            assert!(tree.is_empty());
+        }
+    }
+
+    #[test]
+    fn snapshots_see_the_tree_as_it_stood() {
+        let file = Scratch::new("snapshot");
+        let mut tree = DiskBTree::create_copy_on_write(&file.0, 3).unwrap();
+        tree.set_durability(Durability::Never);
+        for key in 0..1000u32 {
+            tree.insert(key, key).unwrap();
+        }
+        let before = tree.snapshot().unwrap().unwrap();
+        for key in (0..1000).step_by(2) {
+            tree.remove(&key).unwrap();
+        }
+        for key in 0..1000 {
+            tree.insert(key + 1000, 0).unwrap();
+            tree.insert(key | 1, 0).unwrap();
+        }
+        tree.validate().unwrap();
+
+        assert_eq!((before.len(), tree.len()), (1000, 1500));
+        for key in 0..2000 {
+            assert_eq!(before.get(&key).unwrap(), (key < 1000).then_some(key), "key {key}");
+        }
+        assert_eq!(tree.get(&1).unwrap(), Some(0));
+
+        // while it's alive the tree can't reuse what the snapshot reads; after, it can
+        let churn = |tree: &mut DiskBTree<u32, u32>| {
+            let pages = tree.pager.header.pages;
+            for key in 0..500 {
+                tree.insert(key, 1).unwrap();
+            }
+            tree.pager.header.pages - pages
+        };
+        let _held = tree.snapshot().unwrap().unwrap();
+        assert!(churn(&mut tree) > 100);
+        drop((before, _held));
+        assert!(churn(&mut tree) < 5);
+
+        let file = Scratch::new("snapshot-logged");
+        let tree = DiskBTree::<u32, u32>::create(&file.0, 3).unwrap();
+        assert!(tree.snapshot().unwrap().is_none());
+    }
+
+    #[test]
+    fn snapshots_read_on_another_thread() {
+        fn is_send<T: Send>() {}
+        is_send::<DiskBTree<u64, u64>>();
+        is_send::<Snapshot<u64, u64>>();
+
+        let file = Scratch::new("snapshot-thread");
+        let mut tree = DiskBTree::create_copy_on_write(&file.0, 3).unwrap();
+        tree.set_durability(Durability::Never);
+        for key in 0..1000u32 {
+            tree.insert(key, key).unwrap();
+        }
+        let snapshot = tree.snapshot().unwrap().unwrap();
+        let reader = std::thread::spawn(move || (0..1000).all(|key| snapshot.get(&key).unwrap() == Some(key)));
+        for key in 0..1000 {
+            tree.insert(key, 0).unwrap();
+        }
+        assert!(reader.join().unwrap());
+        tree.validate().unwrap();
+        assert_eq!(tree.get(&999).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn a_torn_header_falls_back_to_the_one_before() {
+        let file = Scratch::new("cow-torn");
+        let mut tree = DiskBTree::create_copy_on_write(&file.0, 2).unwrap();
+        for key in 0..100u32 {
+            tree.insert(key, key).unwrap();
+        }
+        let newest = tree.pager.header.txn % 2;
+        mem::forget(tree);
+        // a crash part way through writing the last insert's header
+        let mut bytes = fs::read(&file.0).unwrap();
+        bytes[newest as usize * PAGE_SIZE + 30] ^= 0xFF;
+        fs::write(&file.0, bytes).unwrap();
+
+        let mut tree = DiskBTree::<u32, u32>::open(&file.0).unwrap();
+        tree.validate().unwrap();
+        assert_eq!(tree.len(), 99);
+        assert_eq!(tree.get(&98).unwrap(), Some(98));
+        assert_eq!(tree.get(&99).unwrap(), None);
+        // and carries on from there
+        tree.insert(99, 99).unwrap();
+        drop(tree);
+        let mut tree = DiskBTree::<u32, u32>::open(&file.0).unwrap();
+        tree.validate().unwrap();
+        assert_eq!(tree.len(), 100);
+    }
+
+    #[test]
+    fn a_log_left_beside_a_copy_on_write_file_is_ignored() {
+        let file = Scratch::new("cow-stale-log");
+        let mut tree = DiskBTree::create(&file.0, 2).unwrap();
+        for key in 0..50u32 {
+            tree.insert(key, key + 1000).unwrap();
+        }
+        mem::forget(tree);
+        let log = fs::read(wal_path(&file.0)).unwrap();
+        assert!(!log.is_empty());
+
+        let mut tree = DiskBTree::create_copy_on_write(&file.0, 2).unwrap();
+        assert!(!wal_path(&file.0).exists());
+        for key in 0..100u32 {
+            tree.insert(key, key).unwrap();
+        }
+        let newest = tree.pager.header.txn % 2;
+        mem::forget(tree);
+        // the old tree's log back beside the file, and a torn first header
+        fs::write(wal_path(&file.0), log).unwrap();
+        let mut bytes = fs::read(&file.0).unwrap();
+        bytes[30] ^= 0xFF;
+        fs::write(&file.0, bytes).unwrap();
+
+        let mut tree = DiskBTree::<u32, u32>::open(&file.0).unwrap();
+        assert!(matches!(tree.pager.journal, Journal::Shadow(_)));
+        tree.validate().unwrap();
+        let len = if newest == 0 { 99 } else { 100 };
+        assert_eq!(tree.len(), len);
+        assert_eq!(tree.get(&0).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn copy_on_write_copies_only_what_changes() {
+        let file = Scratch::new("cow-copies");
+        let mut tree = DiskBTree::create_copy_on_write(&file.0, 2).unwrap();
+        tree.set_durability(Durability::Never);
+        for key in 0..2000u32 {
+            tree.insert(key, key).unwrap();
+        }
+        tree.flush().unwrap();
+
+        // a key that isn't there: no copies, no commit
+        let (bytes, txn) = (fs::read(&file.0).unwrap(), tree.pager.header.txn);
+        assert_eq!(tree.remove(&5000).unwrap(), None);
+        assert_eq!(tree.pager.header.txn, txn);
+        tree.flush().unwrap();
+        assert!(fs::read(&file.0).unwrap() == bytes);
+
+        // with a snapshot holding on to every page, each new one is a copy. a remove copies its
+        // path and at most one sibling per level, the one it rotates from or merges into
+        let _held = tree.snapshot().unwrap().unwrap();
+        let mut height = 1;
+        let mut node = tree.load(tree.pager.header.root).unwrap();
+        while !node.is_leaf() {
+            node = tree.load(node.children[0]).unwrap();
+            height += 1;
+        }
+        for key in (0..2000).step_by(3) {
+            let pages = tree.pager.header.pages;
+            tree.remove(&key).unwrap();
+            let copies = tree.pager.header.pages - pages;
+            assert!(copies <= 2 * height, "{copies} copies removing {key} from a tree {height} high");
+        }
+        tree.validate().unwrap();
+    }
+
+    #[test]
+    fn reopened_copy_on_write_trees_reuse_free_pages() {
+        let file = Scratch::new("cow-reopen");
+        let mut tree = DiskBTree::create_copy_on_write(&file.0, 8).unwrap();
+        tree.set_durability(Durability::Never);
+        for key in 0..3000u64 {
+            tree.insert(key, key).unwrap();
+        }
+        for key in 0..3000 {
+            tree.remove(&key).unwrap();
+        }
+        let pages = tree.pager.header.pages;
+        drop(tree);
+
+        let mut tree = DiskBTree::<u64, u64>::open(&file.0).unwrap();
+        assert_eq!(tree.degree(), Some(8));
+        tree.set_durability(Durability::Never);
+        for key in 0..3000 {
+            tree.insert(key, key).unwrap();
+        }
+        tree.validate().unwrap();
+        assert_eq!(tree.pager.header.pages, pages);
+    }
+
+    #[test]
+    fn opening_something_else_fails() {
+        let file = Scratch::new("not-a-tree");
+        fs::write(&file.0, b"Nerevar's Ring").unwrap();
+        assert!(matches!(DiskBTree::<u32, u32>::open(&file.0), Err(DiskError::Corrupt { page: 0, .. })));
+        fs::write(&file.0, vec![0; PAGE_SIZE]).unwrap();
+        assert!(matches!(DiskBTree::<u32, u32>::open(&file.0), Err(DiskError::Corrupt { page: 0, .. })));
+        assert!(matches!(DiskBTree::<u32, u32>::open(file.0.with_extension("missing")), Err(DiskError::Io(_))));
+        // and none of that left a log lying around
+        assert!(!wal_path(&file.0).exists());
+    }
+}
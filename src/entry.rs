@@ -0,0 +1,240 @@
+use std::mem;
+
+use crate::BTree;
+use crate::cursor::{item_at, item_at_mut};
+use crate::node::Item;
+
+/// A view into a single key's spot in a [`BTree`], from [`BTree::entry`].
+///
+/// Looking the key up once remembers the path down to it, so filling,
+/// updating or removing the entry afterwards doesn't search again.
+pub enum Entry<'a, K, V> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// An entry whose key is already in the tree.
+pub struct OccupiedEntry<'a, K, V> {
+    pub(crate) tree: &'a mut BTree<K, V>,
+    pub(crate) path: Vec<usize>,
+}
+
+/// An entry whose key is not in the tree yet. Nothing is split or allocated
+/// until it is filled with [`insert`](VacantEntry::insert).
+pub struct VacantEntry<'a, K, V> {
+    pub(crate) tree: &'a mut BTree<K, V>,
+    pub(crate) key: K,
+    pub(crate) gap: Vec<usize>,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Ord,
+{
+    /// The key this entry is for.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// The value, inserting `default` first if the entry is vacant.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// The value, inserting the result of `default` first if the entry is
+    /// vacant. `default` isn't called for an occupied entry.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Like [`or_insert_with`](Self::or_insert_with), but `default` gets to
+    /// see the key.
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            }
+        }
+    }
+
+    /// The value, inserting `V::default()` first if the entry is vacant.
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Runs `f` on the value if the entry is occupied, then hands the entry
+    /// back for chaining.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V>
+where
+    K: Ord,
+{
+    /// The key as stored in the tree.
+    pub fn key(&self) -> &K {
+        &item_at(&self.tree.root, &self.path).key
+    }
+
+    /// The stored value.
+    pub fn get(&self) -> &V {
+        &item_at(&self.tree.root, &self.path).value
+    }
+
+    /// The stored value, mutably.
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut item_at_mut(&mut self.tree.root, &self.path).value
+    }
+
+    /// The stored value, mutably, for as long as the tree was borrowed.
+    pub fn into_mut(self) -> &'a mut V {
+        &mut item_at_mut(&mut self.tree.root, &self.path).value
+    }
+
+    /// Replaces the value, returning the old one. The stored key is left
+    /// untouched, as with [`BTree::insert`].
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    /// Takes the entry out of the tree, returning its value.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Takes the entry out of the tree, returning the stored key and value.
+    pub fn remove_entry(self) -> (K, V) {
+        let _span = tracing::debug_span!("btree.remove", len = self.tree.len).entered();
+        let (item, _, _) = self.tree.remove_at(self.path);
+        (item.key, item.value)
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V>
+where
+    K: Ord,
+{
+    /// The key that would be inserted.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Gives the key back without inserting anything.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts `value` under the entry's key, splitting full nodes on the way
+    /// down to the spot found by the lookup, and returns it mutably.
+    pub fn insert(self, value: V) -> &'a mut V {
+        let _span = tracing::debug_span!("btree.insert", len = self.tree.len).entered();
+        let path = self.tree.insert_at(self.gap, Item { key: self.key, value });
+        &mut item_at_mut(&mut self.tree.root, &path).value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DEGREES;
+    use crate::node::Node;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn counts_with_one_lookup_each() {
+
+        for degree in DEGREES {
+            let mut btree = BTree::new(degree);
+            let mut expected = BTreeMap::new();
+            for i in 0..2000 {
+                let key = (i * 37) % 301;
+                *btree.entry(key).or_insert(0) += 1;
+                *expected.entry(key).or_insert(0) += 1;
+                if i % 3 == 0 {
+                    *btree.entry(-key).or_default() += 2;
+                    *expected.entry(-key).or_default() += 2;
+                }
+            }
+            btree.validate().unwrap();
+            assert!(btree.iter().eq(expected.iter()));
+        }
+    }
+
+    #[test]
+    fn vacant_entries_only_split_when_filled() {
+
+        // a degree 2 root holding three keys is full
+        let mut btree = BTree::new(2);
+        for key in [10, 20, 30] {
+            btree.insert(key, ());
+        }
+        match btree.entry(25) {
+            Entry::Vacant(entry) => assert_eq!(entry.into_key(), 25),
+            Entry::Occupied(_) => panic!("25 was never inserted"),
+        }
+        assert!(matches!(*btree.root, Node::Leaf(_)));
+        assert_eq!(btree.len(), 3);
+
+        let Entry::Vacant(entry) = btree.entry(25) else { panic!("25 was never inserted") };
+        entry.insert(());
+        assert!(matches!(*btree.root, Node::Internal(_)));
+        assert!(btree.keys().copied().eq([10, 20, 25, 30]));
+        btree.validate().unwrap();
+    }
+
+    #[test]
+    fn occupied_entries_update_and_remove_in_place() {
+
+        for degree in DEGREES {
+            let mut btree = BTree::new(degree);
+            for key in 0..200 {
+                btree.insert(key, key.to_string());
+            }
+
+            let mut calls = 0;
+            let value = btree.entry(50).or_insert_with(|| {
+                calls += 1;
+                String::new()
+            });
+            value.push('!');
+            assert_eq!(calls, 0);
+            assert_eq!(btree.get(&50).map(String::as_str), Some("50!"));
+
+            btree.entry(60).and_modify(|value| value.push('?')).or_insert_with(|| unreachable!());
+            btree.entry(500).and_modify(|_| unreachable!()).or_insert_with_key(|key| format!("{key}*"));
+            assert_eq!(btree.get(&60).map(String::as_str), Some("60?"));
+            assert_eq!(btree.get(&500).map(String::as_str), Some("500*"));
+
+            // remove every key through its entry, internal ones included
+            for key in (0..200).map(|i| (i * 53) % 200) {
+                let Entry::Occupied(mut entry) = btree.entry(key) else { panic!("{key} should be there") };
+                assert_eq!(entry.key(), &key);
+                let old = entry.insert(format!("{key}."));
+                assert_eq!(entry.get().trim_end_matches('.'), old.trim_end_matches(['!', '?']));
+                assert_eq!(entry.remove(), format!("{key}."));
+                btree.validate().unwrap();
+            }
+            assert_eq!(btree.len(), 1);
+            assert_eq!(btree.entry(500).key(), &500);
+        }
+    }
+}
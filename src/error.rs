@@ -0,0 +1,123 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Ways a [`BTree`](crate::BTree) operation can fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BTreeError {
+    /// The key asked for is not in the tree.
+    KeyNotFound,
+    /// A key arrived out of order: a [`CursorMut`](crate::CursorMut) insert
+    /// that doesn't sort strictly between its neighbours at the cursor, or
+    /// "sorted" input that isn't strictly ascending.
+    UnorderedKey,
+    /// [`validate`](crate::BTree::validate) found a broken invariant.
+    ///
+    /// `path` is the list of child indices leading from the root to the
+    /// offending node, so an empty path means the root itself.
+    Invalid { path: Vec<usize>, violation: Violation },
+}
+
+/// Ways a [`DiskBTree`](crate::DiskBTree) operation can fail.
+#[derive(Debug)]
+pub enum DiskError {
+    /// Reading, writing or syncing the file failed.
+    Io(io::Error),
+    /// The file isn't a B-Tree file, or one of its pages doesn't decode.
+    /// `page` 0 is the file's header.
+    Corrupt { page: u64, reason: &'static str },
+    /// An entry encodes to more bytes than a node's page can spare for each
+    /// of its entries.
+    EntryTooBig { bytes: usize, max: usize },
+    /// Every frame in the buffer pool is pinned, leaving nowhere to read
+    /// another page into.
+    PoolExhausted { frames: usize },
+    /// A tree-level failure, such as
+    /// [`validate`](crate::DiskBTree::validate) finding a broken invariant.
+    Tree(BTreeError),
+}
+
+/// A single broken B-Tree invariant, as reported by
+/// [`validate`](crate::BTree::validate).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The key at `index` is not strictly greater than the one before it.
+    UnsortedKeys { index: usize },
+    /// The key at `index` falls outside the range the parent's separators
+    /// allow for this child.
+    KeyOutOfBounds { index: usize },
+    /// A non-root node holds fewer than the minimum number of keys.
+    TooFewKeys { keys: usize, min: usize },
+    /// A node holds more than the maximum number of keys.
+    TooManyKeys { keys: usize, max: usize },
+    /// A node sized by bytes rather than by key count encodes to more bytes
+    /// than its page holds.
+    TooManyBytes { bytes: usize, max: usize },
+    /// An internal node's children don't number one more than its keys.
+    ChildCount { keys: usize, children: usize },
+    /// A leaf sits at a different depth from the first leaf found.
+    UnevenLeaves { depth: usize, expected: usize },
+    /// A cached counter disagrees with what it counts.
+    CounterDrift { counter: &'static str, cached: usize, actual: usize },
+}
+
+impl fmt::Display for BTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BTreeError::KeyNotFound => write!(f, "key not found"),
+            BTreeError::UnorderedKey => write!(f, "key is out of order"),
+            BTreeError::Invalid { path, violation } => write!(f, "invalid node at {path:?}: {violation}"),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::UnsortedKeys { index } => write!(f, "key {index} is out of order"),
+            Violation::KeyOutOfBounds { index } => write!(f, "key {index} is outside its parent's separators"),
+            Violation::TooFewKeys { keys, min } => write!(f, "{keys} keys, below the minimum of {min}"),
+            Violation::TooManyKeys { keys, max } => write!(f, "{keys} keys, above the maximum of {max}"),
+            Violation::TooManyBytes { bytes, max } => write!(f, "{bytes} bytes, above the maximum of {max}"),
+            Violation::ChildCount { keys, children } => write!(f, "{children} children for {keys} keys"),
+            Violation::UnevenLeaves { depth, expected } => write!(f, "leaf at depth {depth}, expected {expected}"),
+            Violation::CounterDrift { counter, cached, actual } => write!(f, "`{counter}` is {cached} but should be {actual}"),
+        }
+    }
+}
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiskError::Io(error) => write!(f, "i/o error: {error}"),
+            DiskError::Corrupt { page, reason } => write!(f, "page {page} is corrupt: {reason}"),
+            DiskError::EntryTooBig { bytes, max } => write!(f, "entry is {bytes} bytes, above the maximum of {max}"),
+            DiskError::PoolExhausted { frames } => write!(f, "all {frames} buffer pool frames are pinned"),
+            DiskError::Tree(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for BTreeError {}
+
+impl Error for DiskError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DiskError::Io(error) => Some(error),
+            DiskError::Tree(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DiskError {
+    fn from(error: io::Error) -> Self {
+        DiskError::Io(error)
+    }
+}
+
+impl From<BTreeError> for DiskError {
+    fn from(error: BTreeError) -> Self {
+        DiskError::Tree(error)
+    }
+}
@@ -0,0 +1,418 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+
+use crate::node::{BTreeRules, Internal, Item, Leaf, Node};
+use crate::{BTree, check_range};
+
+// a subtree standing on its own, with its height (a lone leaf is 1). its root may run thin like
+// any root, but everything under that is a proper B-Tree node
+type Piece<K, V> = (Node<K, V>, usize);
+
+// Joins `left`, `separator` and `right`, whose keys already sort in that order, into one subtree
+// by grafting the shorter piece onto the near edge of the taller one, at the level where their
+// heights match. Touches one node per level of difference.
+pub(crate) fn join<K, V>(left: Piece<K, V>, separator: Item<K, V>, right: Piece<K, V>, rules: &BTreeRules) -> Piece<K, V> {
+    let height = left.1.max(right.1);
+    match graft(left, separator, right, rules) {
+        (node, None) => (node, height),
+        // the overflow ran all the way up, so the two halves get a new root
+        (node, Some((median, sibling))) => {
+            let sizes = if rules.counted { vec![node.size(), sibling.size()] } else { Vec::new() };
+            let root = Internal { items: vec![median], children: vec![node, sibling], sizes };
+            (Node::Internal(root), height + 1)
+        }
+    }
+}
+
+// the joined subtree at the taller piece's height, plus the right half split off of it if the
+// graft left it over full
+type Grafted<K, V> = (Node<K, V>, Option<(Item<K, V>, Node<K, V>)>);
+
+fn graft<K, V>(left: Piece<K, V>, separator: Item<K, V>, right: Piece<K, V>, rules: &BTreeRules) -> Grafted<K, V> {
+    let ((mut left, left_height), (right, right_height)) = (left, right);
+    match left_height.cmp(&right_height) {
+        // level with each other: one node, unless that's too many keys for one node
+        Ordering::Equal => {
+            left.absorb(separator, right);
+            overflow(left, rules)
+        }
+        // down the taller piece's right edge...
+        Ordering::Greater => {
+            let Node::Internal(mut parent) = left else { unreachable!("a taller piece is internal") };
+            let edge = parent.children.pop().unwrap();
+            parent.sizes.pop();
+            let (child, spill) = graft((edge, left_height - 1), separator, (right, right_height), rules);
+            if rules.counted {
+                parent.sizes.push(child.size());
+            }
+            parent.children.push(child);
+            if let Some((median, sibling)) = spill {
+                if rules.counted {
+                    parent.sizes.push(sibling.size());
+                }
+                parent.items.push(median);
+                parent.children.push(sibling);
+            }
+            overflow(Node::Internal(parent), rules)
+        }
+        // ...or its left edge
+        Ordering::Less => {
+            let Node::Internal(mut parent) = right else { unreachable!("a taller piece is internal") };
+            let edge = parent.children.remove(0);
+            if rules.counted {
+                parent.sizes.remove(0);
+            }
+            let (child, spill) = graft((left, left_height), separator, (edge, right_height - 1), rules);
+            if let Some((median, sibling)) = spill {
+                if rules.counted {
+                    parent.sizes.insert(0, sibling.size());
+                }
+                parent.items.insert(0, median);
+                parent.children.insert(0, sibling);
+            }
+            if rules.counted {
+                parent.sizes.insert(0, child.size());
+            }
+            parent.children.insert(0, child);
+            overflow(Node::Internal(parent), rules)
+        }
+    }
+}
+
+fn overflow<K, V>(mut node: Node<K, V>, rules: &BTreeRules) -> Grafted<K, V> {
+    // at most one node's worth over, so both halves of a split come out with enough keys
+    if node.items().len() <= rules.maxkeys {
+        return (node, None);
+    }
+    let (median, sibling) = node.split();
+    (node, Some((median, sibling)))
+}
+
+// Cuts a subtree in two along the search path for `key`: everything below `key` to the left,
+// everything above to the right, and `key` itself to the left only if `inclusive`. The nodes off
+// the path stay whole, each side's are joined back up.
+pub(crate) fn cut<K, V, Q>(
+    node: Node<K, V>,
+    height: usize,
+    key: &Q,
+    inclusive: bool,
+    rules: &BTreeRules,
+) -> (Piece<K, V>, Piece<K, V>)
+where
+    K: Ord + Borrow<Q>,
+    Q: Ord + ?Sized,
+{
+    let (position, found) = node.binary_search(key);
+    let position = position + usize::from(found && inclusive);
+    match node {
+        Node::Leaf(mut leaf) => {
+            let items = leaf.items.split_off(position);
+            ((Node::Leaf(leaf), height), (Node::Leaf(Leaf { items }), height))
+        }
+        Node::Internal(Internal { mut items, mut children, mut sizes }) => {
+            // child `position` straddles the cut, the ones either side of it go over whole
+            let mut right_items = items.split_off(position);
+            let right_children = children.split_off(position + 1);
+            let right_sizes = if rules.counted { sizes.split_off(position + 1) } else { Vec::new() };
+            let straddling = children.pop().unwrap();
+            sizes.pop();
+            let (low, high) = cut(straddling, height - 1, key, inclusive, rules);
+
+            let left = match items.pop() {
+                None => low,
+                Some(separator) => join(remains(items, children, sizes, height), separator, low, rules),
+            };
+            let right = if right_items.is_empty() {
+                high
+            } else {
+                let separator = right_items.remove(0);
+                join(high, separator, remains(right_items, right_children, right_sizes, height), rules)
+            };
+            (left, right)
+        }
+    }
+}
+
+// what's left of an internal node once the cut took its separator: itself, or its one child
+fn remains<K, V>(items: Vec<Item<K, V>>, mut children: Vec<Node<K, V>>, sizes: Vec<usize>, height: usize) -> Piece<K, V> {
+    if items.is_empty() {
+        return (children.pop().unwrap(), height - 1);
+    }
+    (Node::Internal(Internal { items, children, sizes }), height)
+}
+
+// items in a subtree, for trees that don't keep sizes
+fn count<K, V>(node: &Node<K, V>) -> usize {
+    node.items().len() + node.children().iter().map(count).sum::<usize>()
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Ord,
+{
+    /// Splits the tree in two at `key`: every entry from `key` up moves into
+    /// the returned tree, which has the same degree, and the rest stay.
+    ///
+    /// Only the nodes along the search path for `key` are taken apart; the
+    /// subtrees either side of it move over whole and are grafted back
+    /// together. On a tree made with
+    /// [`with_subtree_sizes`](Self::with_subtree_sizes) that's all there is
+    /// to it, `O(log n)`. Any other tree has no way to know how many entries
+    /// went either way without counting them, so it also walks every node of
+    /// the shorter half, which makes it `O(n)` in the worst case.
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::debug_span!("btree.split_off", len = self.len).entered();
+        self.split_at(key, false)
+    }
+
+    /// Removes every entry whose key falls in `range` and hands them back as
+    /// a tree of their own, with the same degree.
+    ///
+    /// The tree is cut along the search paths for the two ends of the range
+    /// and what's either side of it grafted back together, so whole subtrees
+    /// inside the range move out without being visited and only the nodes
+    /// along those two paths get rebalanced. That's `O(log n)` on a tree with
+    /// subtree sizes, but as with [`split_off`](Self::split_off), any other
+    /// tree also walks the nodes of one side of each cut to learn the lengths,
+    /// `O(n)` in the worst case.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range starts after it ends, or if both ends are the same
+    /// excluded key.
+    pub fn remove_range<Q, R>(&mut self, range: R) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let _span = tracing::debug_span!("btree.remove_range", len = self.len).entered();
+        check_range(&range);
+        let mut removed = match range.start_bound() {
+            Bound::Included(start) => self.split_at(start, false),
+            Bound::Excluded(start) => self.split_at(start, true),
+            Bound::Unbounded => self.take(),
+        };
+        let mut after = match range.end_bound() {
+            Bound::Included(end) => removed.split_at(end, true),
+            Bound::Excluded(end) => removed.split_at(end, false),
+            Bound::Unbounded => return removed,
+        };
+        self.append(&mut after);
+        removed
+    }
+
+    // everything above `key` (and `key` itself, unless `inclusive`) moves out into the tree returned
+    fn split_at<Q>(&mut self, key: &Q, inclusive: bool) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let rules = self.rules;
+        let whole = self.take();
+        let height = whole.height();
+        let ((left, left_height), (right, right_height)) = cut(*whole.root, height, key, inclusive, &rules);
+        let right_len = if rules.counted {
+            right.size()
+        } else if right_height <= left_height {
+            count(&right)
+        } else {
+            whole.len - count(&left)
+        };
+        tracing::debug!(left_height, right_height, left = whole.len - right_len, right = right_len, "split off");
+
+        *self.root = left;
+        self.len = whole.len - right_len;
+        BTree { root: Box::new(right), len: right_len, rules }
+    }
+
+    /// Moves every entry of `other` into this tree, leaving `other` empty.
+    /// Where both hold a key, `other`'s value wins.
+    ///
+    /// When the two trees share a degree and their keys don't interleave (all
+    /// of one sorts below all of the other), the shorter tree is grafted onto
+    /// the edge of the taller one in `O(log n)`. Otherwise the entries are
+    /// merged as by [`extend`](Extend::extend).
+    pub fn append(&mut self, other: &mut Self) {
+        let _span = tracing::debug_span!("btree.append", len = self.len, other = other.len).entered();
+        if other.is_empty() {
+            return;
+        }
+        let below = |lower: &Self, upper: &Self| match (lower.last_key_value(), upper.first_key_value()) {
+            (Some((last, _)), Some((first, _))) => last < first,
+            _ => true,
+        };
+        if self.rules != other.rules {
+            self.extend(other.take());
+        } else if below(self, other) {
+            self.concat(other.take());
+        } else if below(other, self) {
+            mem::swap(self, other);
+            self.concat(other.take());
+        } else {
+            self.extend(other.take());
+        }
+    }
+
+    // grafts `upper`, non-empty and with every key above ours, onto our right edge
+    pub(crate) fn concat(&mut self, mut upper: Self) {
+        let (key, value) = upper.pop_first().unwrap();
+        let lower = self.take();
+        let (left_height, right_height) = (lower.height(), upper.height());
+        let len = lower.len + 1 + upper.len;
+        let (root, height) = join((*lower.root, left_height), Item { key, value }, (*upper.root, right_height), &self.rules);
+        tracing::debug!(left_height, right_height, height, "grafted trees");
+
+        *self.root = root;
+        self.len = len;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DEGREES;
+    use std::collections::BTreeMap;
+
+    // both flavours of tree, with keys inserted out of order so the nodes aren't all packed
+    fn trees(degree: usize, keys: impl Iterator<Item = i32> + Clone) -> [BTree<i32, i32>; 2] {
+        [BTree::new(degree), BTree::with_subtree_sizes(degree)].map(|mut btree| {
+            for key in keys.clone() {
+                btree.insert(key, key * 10);
+            }
+            btree
+        })
+    }
+
+    fn scattered(n: i32) -> impl Iterator<Item = i32> + Clone {
+        (0..n).map(move |i| (i * 37) % n * 2)
+    }
+
+    #[test]
+    fn split_off_matches_std() {
+        for degree in DEGREES {
+            let expected: BTreeMap<i32, i32> = scattered(101).map(|key| (key, key * 10)).collect();
+            for flavour in 0..2 {
+                // every key, every gap between them, and past both ends
+                for at in -1..203 {
+                    let [plain, counted] = trees(degree, scattered(101));
+                    let mut left = [plain, counted].into_iter().nth(flavour).unwrap();
+                    let right = left.split_off(&at);
+                    let mut expected_left = expected.clone();
+                    let expected_right = expected_left.split_off(&at);
+
+                    left.validate().unwrap();
+                    right.validate().unwrap();
+                    assert_eq!(left.len(), expected_left.len(), "split at {at}, degree {degree}");
+                    assert_eq!(right.len(), expected_right.len(), "split at {at}, degree {degree}");
+                    assert!(left.iter().eq(expected_left.iter()));
+                    assert!(right.iter().eq(expected_right.iter()));
+                    assert_eq!(right.degree(), degree);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn append_grafts_trees_of_any_height() {
+        for degree in DEGREES {
+            for small in [0, 1, 5, 40, 700] {
+                let lows = trees(degree, 0..700).into_iter().zip(trees(degree, 0..700));
+                let highs = trees(degree, 1000..1000 + small).into_iter().zip(trees(degree, 1000..1000 + small));
+                for ((mut tall, mut below), (mut short, mut above)) in lows.zip(highs) {
+                    // the short tree goes on either side of the tall one, whichever is `self`
+                    tall.append(&mut short);
+                    above.append(&mut below);
+
+                    for (btree, emptied) in [(tall, short), (above, below)] {
+                        btree.validate().unwrap();
+                        assert!(emptied.is_empty());
+                        assert_eq!(btree.len(), 700 + small as usize);
+                        assert!(btree.keys().copied().eq((0..700).chain(1000..1000 + small)));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn append_merges_interleaved_trees() {
+        for degree in DEGREES {
+            let [mut evens, mut odds] = trees(degree, (0..300).map(|key| key * 2));
+            let [mut others, mut counted_others] = trees(degree, (0..200).map(|key| key * 3));
+            for other in [&mut others, &mut counted_others] {
+                for (_, value) in other.iter_mut() {
+                    *value = -1;
+                }
+            }
+            let mut expected: BTreeMap<i32, i32> = (0..300).map(|key| (key * 2, key * 20)).collect();
+            expected.extend((0..200).map(|key| (key * 3, -1)));
+
+            evens.append(&mut others);
+            odds.append(&mut counted_others);
+            for btree in [evens, odds] {
+                btree.validate().unwrap();
+                assert!(btree.iter().eq(expected.iter()));
+            }
+        }
+    }
+
+    #[test]
+    fn split_off_and_append_round_trip() {
+        for degree in DEGREES {
+            for (mut btree, original) in trees(degree, scattered(500)).into_iter().zip(trees(degree, scattered(500))) {
+                let mut pieces = Vec::new();
+                for at in [800, 600, 130, 129, 2] {
+                    pieces.push(btree.split_off(&at));
+                }
+                while let Some(mut piece) = pieces.pop() {
+                    btree.append(&mut piece);
+                    btree.validate().unwrap();
+                }
+                assert!(btree.iter().eq(original.iter()));
+                assert_eq!(btree.len(), original.len());
+            }
+        }
+    }
+
+    #[test]
+    fn remove_range_matches_std() {
+        let bounds = [-5, 0, 1, 2, 37, 38, 100, 101, 198, 199, 250];
+        for degree in DEGREES {
+            for start in bounds {
+                for end in bounds.into_iter().filter(|end| *end > start) {
+                    let ranges = [
+                        (Bound::Included(start), Bound::Excluded(end)),
+                        (Bound::Excluded(start), Bound::Included(end)),
+                        (Bound::Unbounded, Bound::Included(end)),
+                        (Bound::Included(start), Bound::Unbounded),
+                    ];
+                    for range in ranges {
+                        let expected: BTreeMap<i32, i32> = scattered(100).map(|key| (key, key * 10)).collect();
+                        let (expected_removed, expected_kept): (BTreeMap<i32, i32>, BTreeMap<i32, i32>) =
+                            expected.into_iter().partition(|(key, _)| range.contains(key));
+                        for mut btree in trees(degree, scattered(100)) {
+                            let removed = btree.remove_range(range);
+                            btree.validate().unwrap();
+                            removed.validate().unwrap();
+                            assert_eq!(btree.len(), expected_kept.len(), "{range:?} at degree {degree}");
+                            assert_eq!(removed.len(), expected_removed.len(), "{range:?} at degree {degree}");
+                            assert!(btree.iter().eq(expected_kept.iter()));
+                            assert!(removed.iter().eq(expected_removed.iter()));
+                        }
+                    }
+                }
+            }
+            let [mut btree, _] = trees(degree, scattered(100));
+            assert_eq!(btree.remove_range::<i32, _>(..).len(), 100);
+            assert!(btree.is_empty());
+            btree.validate().unwrap();
+        }
+    }
+}
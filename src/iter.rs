@@ -0,0 +1,382 @@
+use std::borrow::Borrow;
+use std::iter::FusedIterator;
+use std::ops::{self, Bound};
+use std::{slice, vec};
+
+use crate::node::{Item, Node};
+
+// A way of holding a node while we walk it: shared, exclusive or owned. Each one knows how
+// to open a node up into iterators over (a window of) its items and children, so a single
+// traversal can hand out `&Item`, `&mut Item` or `Item` without any unsafe.
+pub(crate) trait Handle: Sized {
+    type K;
+    type V;
+    type Item;
+    type Items: DoubleEndedIterator<Item = Self::Item>;
+    type Children: DoubleEndedIterator<Item = Self>;
+
+    fn node(&self) -> &Node<Self::K, Self::V>;
+    fn open(self, items: ops::Range<usize>, children: ops::Range<usize>) -> (Self::Items, Self::Children);
+}
+
+impl<'a, K, V> Handle for &'a Node<K, V> {
+    type K = K;
+    type V = V;
+    type Item = &'a Item<K, V>;
+    type Items = slice::Iter<'a, Item<K, V>>;
+    type Children = slice::Iter<'a, Node<K, V>>;
+
+    fn node(&self) -> &Node<K, V> {
+        self
+    }
+    fn open(self, items: ops::Range<usize>, children: ops::Range<usize>) -> (Self::Items, Self::Children) {
+        (self.items()[items].iter(), self.children()[children].iter())
+    }
+}
+
+impl<'a, K, V> Handle for &'a mut Node<K, V> {
+    type K = K;
+    type V = V;
+    type Item = &'a mut Item<K, V>;
+    type Items = slice::IterMut<'a, Item<K, V>>;
+    type Children = slice::IterMut<'a, Node<K, V>>;
+
+    fn node(&self) -> &Node<K, V> {
+        self
+    }
+    fn open(self, items: ops::Range<usize>, children: ops::Range<usize>) -> (Self::Items, Self::Children) {
+        match self {
+            Node::Leaf(leaf) => (leaf.items[items].iter_mut(), [].iter_mut()),
+            Node::Internal(internal) => (internal.items[items].iter_mut(), internal.children[children].iter_mut()),
+        }
+    }
+}
+
+impl<K, V> Handle for Node<K, V> {
+    type K = K;
+    type V = V;
+    type Item = Item<K, V>;
+    type Items = vec::IntoIter<Item<K, V>>;
+    type Children = vec::IntoIter<Node<K, V>>;
+
+    fn node(&self) -> &Node<K, V> {
+        self
+    }
+    fn open(self, items: ops::Range<usize>, children: ops::Range<usize>) -> (Self::Items, Self::Children) {
+        fn window<T>(mut all: Vec<T>, range: ops::Range<usize>) -> vec::IntoIter<T> {
+            all.truncate(range.end);
+            all.drain(..range.start);
+            all.into_iter()
+        }
+        match self {
+            Node::Leaf(leaf) => (window(leaf.items, items), Vec::new().into_iter()),
+            Node::Internal(internal) => (window(internal.items, items), window(internal.children, children)),
+        }
+    }
+}
+
+// One opened node. In order, a node reads child 0, item 0, child 1, item 1, ... child n, so each
+// end remembers whether it owes a child or an item next. Both ends pull from the same iterators,
+// which is what stops them handing out anything twice when they meet in the same node.
+pub(crate) struct Frame<H: Handle> {
+    pub(crate) items: H::Items,
+    pub(crate) children: H::Children,
+    pub(crate) front_child: bool,
+    pub(crate) back_child: bool,
+}
+
+impl<H: Handle> Frame<H> {
+    pub(crate) fn new(handle: H, items: ops::Range<usize>, children: ops::Range<usize>) -> Self {
+        let (items, children) = handle.open(items, children);
+        Frame { items, children, front_child: true, back_child: true }
+    }
+    fn whole(handle: H) -> Self {
+        let node = handle.node();
+        let (items, children) = (0..node.items().len(), 0..node.children().len());
+        Frame::new(handle, items, children)
+    }
+}
+
+// The two ends of an in-order walk. `front` and `back` are the paths each end has descended, and
+// together they form one line: the front walks its own stack and, once that runs dry, carries on
+// into the bottom of the back's stack (and the other way round). A frame is dropped by whichever
+// end finds it empty first.
+pub(crate) struct Traversal<H: Handle> {
+    pub(crate) front: Vec<Frame<H>>,
+    pub(crate) back: Vec<Frame<H>>,
+}
+
+impl<H: Handle> Traversal<H> {
+    pub(crate) fn new(root: H) -> Self {
+        Traversal { front: vec![Frame::whole(root)], back: Vec::new() }
+    }
+    pub(crate) fn next(&mut self) -> Option<H::Item> {
+        loop {
+            let frame = match self.front.last_mut() {
+                Some(frame) => frame,
+                None => self.back.first_mut()?,
+            };
+            if frame.front_child {
+                frame.front_child = false;
+                if let Some(child) = frame.children.next() {
+                    self.front.push(Frame::whole(child));
+                    continue;
+                }
+            }
+            if let Some(item) = frame.items.next() {
+                frame.front_child = true;
+                return Some(item);
+            }
+            // nothing left in this node from where we're standing
+            if self.front.pop().is_none() {
+                self.back.remove(0);
+            }
+        }
+    }
+    pub(crate) fn next_back(&mut self) -> Option<H::Item> {
+        loop {
+            let frame = match self.back.last_mut() {
+                Some(frame) => frame,
+                None => self.front.first_mut()?,
+            };
+            if frame.back_child {
+                frame.back_child = false;
+                if let Some(child) = frame.children.next_back() {
+                    self.back.push(Frame::whole(child));
+                    continue;
+                }
+            }
+            if let Some(item) = frame.items.next_back() {
+                frame.back_child = true;
+                return Some(item);
+            }
+            if self.back.pop().is_none() {
+                self.front.remove(0);
+            }
+        }
+    }
+}
+
+// first item at or past the lower bound
+fn lower_edge<K, V, Q>(node: &Node<K, V>, bound: Bound<&Q>) -> usize
+where
+    K: Borrow<Q> + Ord,
+    Q: Ord + ?Sized,
+{
+    match bound {
+        Bound::Unbounded => 0,
+        Bound::Included(key) => node.binary_search(key).0,
+        Bound::Excluded(key) => match node.binary_search(key) {
+            (index, true) => index + 1,
+            (index, false) => index,
+        },
+    }
+}
+
+// one past the last item at or before the upper bound
+fn upper_edge<K, V, Q>(node: &Node<K, V>, bound: Bound<&Q>) -> usize
+where
+    K: Borrow<Q> + Ord,
+    Q: Ord + ?Sized,
+{
+    match bound {
+        Bound::Unbounded => node.items().len(),
+        Bound::Included(key) => match node.binary_search(key) {
+            (index, true) => index + 1,
+            (index, false) => index,
+        },
+        Bound::Excluded(key) => node.binary_search(key).0,
+    }
+}
+
+// children that can hold keys between items[low..high] are children[low..=high]
+fn edge_children<K, V>(node: &Node<K, V>, low: usize, high: usize) -> ops::Range<usize> {
+    match node {
+        Node::Leaf(_) => 0..0,
+        Node::Internal(_) => low..high + 1,
+    }
+}
+
+impl<H> Traversal<H>
+where
+    H: Handle,
+    H::K: Ord,
+{
+    // Walk down while both bounds land in the same child, open the node where they part with
+    // just the items between them, then send each end down its own side to a leaf. Everything
+    // the ends open after that lies wholly inside the range, so stepping needs no more compares.
+    pub(crate) fn range<Q>(root: H, lower: Bound<&Q>, upper: Bound<&Q>) -> Self
+    where
+        H::K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut handle = root;
+        let junction = loop {
+            let node = handle.node();
+            let (low, high) = (lower_edge(node, lower), upper_edge(node, upper));
+            let children = edge_children(node, low, high);
+            if low < high || children.is_empty() {
+                break Frame::new(handle, low..high, children);
+            }
+            let (_, mut only) = handle.open(low..low, children);
+            handle = only.next().expect("an internal node has a child on every edge");
+        };
+        let mut traversal = Traversal { front: vec![junction], back: Vec::new() };
+
+        loop {
+            let frame = traversal.front.last_mut().expect("the junction is still on the stack");
+            frame.front_child = false;
+            let Some(child) = frame.children.next() else { break };
+            let node = child.node();
+            let low = lower_edge(node, lower);
+            let (items, children) = (low..node.items().len(), edge_children(node, low, node.items().len()));
+            traversal.front.push(Frame::new(child, items, children));
+        }
+        loop {
+            let frame = match traversal.back.last_mut() {
+                Some(frame) => frame,
+                None => traversal.front.first_mut().expect("the junction is still on the stack"),
+            };
+            frame.back_child = false;
+            let Some(child) = frame.children.next_back() else { break };
+            let node = child.node();
+            let high = upper_edge(node, upper);
+            let children = edge_children(node, 0, high);
+            traversal.back.push(Frame::new(child, 0..high, children));
+        }
+        traversal
+    }
+}
+
+/// An iterator over a tree's entries in key order, from [`BTree::iter`](crate::BTree::iter).
+pub struct Iter<'a, K, V> {
+    pub(crate) inner: Traversal<&'a Node<K, V>>,
+    pub(crate) length: usize,
+}
+
+/// A mutable iterator over a tree's entries in key order, from
+/// [`BTree::iter_mut`](crate::BTree::iter_mut).
+pub struct IterMut<'a, K, V> {
+    pub(crate) inner: Traversal<&'a mut Node<K, V>>,
+    pub(crate) length: usize,
+}
+
+/// An owning iterator over a tree's entries in key order, from
+/// [`BTree::into_iter`](crate::BTree::into_iter).
+pub struct IntoIter<K, V> {
+    pub(crate) inner: Traversal<Node<K, V>>,
+    pub(crate) length: usize,
+}
+
+/// An iterator over the entries whose keys fall in a range, from
+/// [`BTree::range`](crate::BTree::range).
+pub struct Range<'a, K, V> {
+    pub(crate) inner: Traversal<&'a Node<K, V>>,
+}
+
+/// A mutable iterator over the entries whose keys fall in a range, from
+/// [`BTree::range_mut`](crate::BTree::range_mut).
+pub struct RangeMut<'a, K, V> {
+    pub(crate) inner: Traversal<&'a mut Node<K, V>>,
+}
+
+/// An iterator over a tree's keys in order, from [`BTree::keys`](crate::BTree::keys).
+pub struct Keys<'a, K, V> {
+    pub(crate) inner: Iter<'a, K, V>,
+}
+
+/// An iterator over a tree's values in key order, from [`BTree::values`](crate::BTree::values).
+pub struct Values<'a, K, V> {
+    pub(crate) inner: Iter<'a, K, V>,
+}
+
+/// A mutable iterator over a tree's values in key order, from
+/// [`BTree::values_mut`](crate::BTree::values_mut).
+pub struct ValuesMut<'a, K, V> {
+    pub(crate) inner: IterMut<'a, K, V>,
+}
+
+// the full-tree iterators know exactly how much is left, so they count down rather than rely on
+// the two ends noticing each other
+macro_rules! counted {
+    ($name:ident<$($lt:lifetime,)? $k:ident, $v:ident>, $item:ty, |$entry:ident| $map:expr) => {
+        impl<$($lt,)? $k, $v> Iterator for $name<$($lt,)? $k, $v> {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                if self.length == 0 {
+                    return None;
+                }
+                self.length -= 1;
+                self.inner.next().map(|$entry| $map)
+            }
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                (self.length, Some(self.length))
+            }
+        }
+        impl<$($lt,)? $k, $v> DoubleEndedIterator for $name<$($lt,)? $k, $v> {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                if self.length == 0 {
+                    return None;
+                }
+                self.length -= 1;
+                self.inner.next_back().map(|$entry| $map)
+            }
+        }
+        impl<$($lt,)? $k, $v> ExactSizeIterator for $name<$($lt,)? $k, $v> {}
+        impl<$($lt,)? $k, $v> FusedIterator for $name<$($lt,)? $k, $v> {}
+    };
+}
+
+counted!(Iter<'a, K, V>, (&'a K, &'a V), |item| (&item.key, &item.value));
+counted!(IterMut<'a, K, V>, (&'a K, &'a mut V), |item| (&item.key, &mut item.value));
+counted!(IntoIter<K, V>, (K, V), |item| (item.key, item.value));
+
+// a range doesn't know its length up front, so it runs until the two ends meet
+macro_rules! bounded {
+    ($name:ident<$lt:lifetime, $k:ident, $v:ident>, $item:ty, |$entry:ident| $map:expr) => {
+        impl<$lt, $k, $v> Iterator for $name<$lt, $k, $v> {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.inner.next().map(|$entry| $map)
+            }
+        }
+        impl<$lt, $k, $v> DoubleEndedIterator for $name<$lt, $k, $v> {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                self.inner.next_back().map(|$entry| $map)
+            }
+        }
+        impl<$lt, $k, $v> FusedIterator for $name<$lt, $k, $v> {}
+    };
+}
+
+bounded!(Range<'a, K, V>, (&'a K, &'a V), |item| (&item.key, &item.value));
+bounded!(RangeMut<'a, K, V>, (&'a K, &'a mut V), |item| (&item.key, &mut item.value));
+
+// the projections just pick a side of what the entry iterators hand out
+macro_rules! projected {
+    ($name:ident<$lt:lifetime, $k:ident, $v:ident>, $item:ty, |$entry:ident| $map:expr) => {
+        impl<$lt, $k, $v> Iterator for $name<$lt, $k, $v> {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.inner.next().map(|$entry| $map)
+            }
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                self.inner.size_hint()
+            }
+        }
+        impl<$lt, $k, $v> DoubleEndedIterator for $name<$lt, $k, $v> {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                self.inner.next_back().map(|$entry| $map)
+            }
+        }
+        impl<$lt, $k, $v> ExactSizeIterator for $name<$lt, $k, $v> {}
+        impl<$lt, $k, $v> FusedIterator for $name<$lt, $k, $v> {}
+    };
+}
+
+projected!(Keys<'a, K, V>, &'a K, |entry| entry.0);
+projected!(Values<'a, K, V>, &'a V, |entry| entry.1);
+projected!(ValuesMut<'a, K, V>, &'a mut V, |entry| entry.1);
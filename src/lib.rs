@@ -0,0 +1,1463 @@
+//! An in-memory B-Tree using a proactive, single, downward pass for both
+//! insert and delete. [`DiskBTree`] runs the same passes over a file of
+//! fixed-size pages.
+//!
+//! Restructuring is reported through [`tracing`]: every public operation runs
+//! inside a span, and root splits, child splits, rotations, merges and height
+//! changes are emitted as `DEBUG` events carrying the depth (root is 0) and
+//! position of the parent whose children changed, plus how many items the
+//! nodes involved hold. Keys are never logged, so they need no `Debug`.
+//!
+//! ```
+//! use btree::{BTree, BTreeError};
+//!
+//! let mut tree = BTree::new(2);
+//! tree.insert(23, "Nerevar's Ring");
+//! tree.insert(67, "Vivec's Tears");
+//!
+//! assert_eq!(tree.get(&23), Some(&"Nerevar's Ring"));
+//! assert_eq!(tree.len(), 2);
+//!
+//! assert_eq!(tree.insert(23, "Ring of Phynaster"), Some("Nerevar's Ring"));
+//! assert_eq!(tree.remove(&67), Some("Vivec's Tears"));
+//! assert_eq!(tree.try_remove(&67), Err(BTreeError::KeyNotFound));
+//! ```
+
+mod bulk;
+mod codec;
+mod cursor;
+mod disk;
+mod entry;
+mod error;
+mod graft;
+mod iter;
+mod node;
+mod pager;
+mod pool;
+mod shadow;
+mod wal;
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Display};
+use std::iter::Peekable;
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+
+pub use codec::Codec;
+pub use cursor::{Cursor, CursorMut, ExtractIf};
+pub use disk::DiskBTree;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use error::{BTreeError, DiskError, Violation};
+pub use node::BTreeRules;
+pub use iter::{IntoIter, Iter, IterMut, Keys, Range, RangeMut, Values, ValuesMut};
+pub use pool::{Eviction, PoolStats};
+pub use shadow::Snapshot;
+pub use wal::Durability;
+use iter::Traversal;
+use node::{Internal, Item, Node};
+
+// the degree `Default`, `FromIterator` and friends build with
+const DEFAULT_DEGREE: usize = 6;
+
+// every shape-agnostic test runs once per degree, from the tallest trees to a single fat leaf
+#[cfg(test)]
+pub(crate) const DEGREES: [usize; 5] = [2, 3, 8, 64, 512];
+
+/// A sorted map backed by a B-Tree of a fixed minimum degree.
+#[derive(Debug)]
+pub struct BTree<K, V> {
+    root: Box<Node<K, V>>,
+    len: usize,
+    rules: BTreeRules,
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Ord,
+{
+    /// Makes a new, empty tree where every node holds between `degree - 1`
+    /// and `2 * degree - 1` keys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `degree` is less than 2.
+    pub fn new(degree: usize) -> Self {
+        BTree::with_rules(BTreeRules::new(degree))
+    }
+
+    /// Makes a new, empty tree whose nodes are sized by `rules`, such as
+    /// [`BTreeRules::for_node_size`] picks to fit nodes to a byte budget.
+    pub fn with_rules(rules: BTreeRules) -> Self {
+        BTree {
+            root: Box::new(Node::new(&rules)),
+            len: 0,
+            rules,
+        }
+    }
+
+    /// Like [`new`](Self::new), but every internal node also keeps the size
+    /// of each child's subtree, so [`nth`](Self::nth), [`rank`](Self::rank)
+    /// and [`count_range`](Self::count_range) run in `O(log n)` instead of
+    /// walking the entries, and so do [`split_off`](Self::split_off) and
+    /// [`remove_range`](Self::remove_range). Costs one `usize` per child and
+    /// a little upkeep on every insert and remove.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `degree` is less than 2.
+    pub fn with_subtree_sizes(degree: usize) -> Self {
+        BTree::with_rules(BTreeRules { counted: true, ..BTreeRules::new(degree) })
+    }
+
+    /// The minimum degree this tree was built with.
+    pub fn degree(&self) -> usize {
+        self.rules.degree
+    }
+
+    /// Number of key-value pairs in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// `true` if the tree holds no key-value pairs.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a reference to the value stored under `key`, if any.
+    ///
+    /// `key` may be any borrowed form of the tree's key type, so a
+    /// `BTree<String, _>` can be probed with a `&str`.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::trace_span!("btree.get").entered();
+        self.root.get(key).map(|item| &item.value)
+    }
+
+    /// Returns a mutable reference to the value stored under `key`, if any.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::trace_span!("btree.get_mut").entered();
+        self.root.get_mut(key).map(|item| &mut item.value)
+    }
+
+    /// Returns the stored key and its value for `key`, if any.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::trace_span!("btree.get_key_value").entered();
+        self.root.get(key).map(|item| (&item.key, &item.value))
+    }
+
+    /// `true` if the tree holds a value for `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::trace_span!("btree.contains_key").entered();
+        self.root.get(key).is_some()
+    }
+
+    /// The entry with the smallest key, if any.
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = &*self.root;
+        while let Node::Internal(internal) = node {
+            node = &internal.children[0];
+        }
+        node.items().first().map(|item| (&item.key, &item.value))
+    }
+
+    /// The entry with the largest key, if any.
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = &*self.root;
+        while let Node::Internal(internal) = node {
+            node = internal.children.last().unwrap();
+        }
+        node.items().last().map(|item| (&item.key, &item.value))
+    }
+
+    /// The entry at position `index` in key order (0 being the smallest),
+    /// if the tree is that big.
+    pub fn nth(&self, mut index: usize) -> Option<(&K, &V)> {
+        let _span = tracing::trace_span!("btree.nth", index).entered();
+        if index >= self.len {
+            return None;
+        }
+        if !self.rules.counted {
+            return self.iter().nth(index);
+        }
+        let mut node = &*self.root;
+        while let Node::Internal(internal) = node {
+            // skip whole children, and the separator after each, until the index lands in one
+            let mut position = 0;
+            while index >= internal.sizes[position] {
+                index -= internal.sizes[position];
+                if index == 0 {
+                    let item = &internal.items[position];
+                    return Some((&item.key, &item.value));
+                }
+                index -= 1;
+                position += 1;
+            }
+            node = &internal.children[position];
+        }
+        let item = &node.items()[index];
+        Some((&item.key, &item.value))
+    }
+
+    /// How many keys in the tree are smaller than `key`. For a key that is
+    /// present, that's its position in key order.
+    pub fn rank<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::trace_span!("btree.rank").entered();
+        self.count_below(key, false)
+    }
+
+    /// How many keys fall in `range`, without visiting them when the tree
+    /// keeps subtree sizes.
+    ///
+    /// # Panics
+    ///
+    /// Panics on the same malformed ranges as [`range`](Self::range).
+    pub fn count_range<Q, R>(&self, range: R) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let _span = tracing::trace_span!("btree.count_range").entered();
+        check_range(&range);
+        if !self.rules.counted {
+            return self.range(range).count();
+        }
+        let below_start = match range.start_bound() {
+            Bound::Included(key) => self.count_below(key, false),
+            Bound::Excluded(key) => self.count_below(key, true),
+            Bound::Unbounded => 0,
+        };
+        let up_to_end = match range.end_bound() {
+            Bound::Included(key) => self.count_below(key, true),
+            Bound::Excluded(key) => self.count_below(key, false),
+            Bound::Unbounded => self.len,
+        };
+        up_to_end - below_start
+    }
+
+    fn count_below<Q>(&self, key: &Q, inclusive: bool) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        if !self.rules.counted {
+            let end = if inclusive { Bound::Included(key) } else { Bound::Excluded(key) };
+            return self.range((Bound::Unbounded, end)).count();
+        }
+        let mut count = 0;
+        let mut node = &*self.root;
+        loop {
+            // everything left of `position` in this node, and the key itself if it counts
+            let (position, found) = node.binary_search(key);
+            count += position + usize::from(found && inclusive);
+            match node {
+                Node::Leaf(_) => return count,
+                Node::Internal(internal) => {
+                    count += internal.sizes[..position].iter().sum::<usize>();
+                    if found {
+                        // the child left of a found key lies wholly below it
+                        return count + internal.sizes[position];
+                    }
+                    node = &internal.children[position];
+                }
+            }
+        }
+    }
+
+    /// Iterates, in key order, over the entries whose keys fall in `range`.
+    ///
+    /// Either end may be inclusive, exclusive or unbounded, so `tree.range(4..)`,
+    /// `tree.range(..=9)` and `tree.range((Excluded(4), Included(9)))` all work.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range starts after it ends, or if both ends are the same
+    /// excluded key.
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let _span = tracing::trace_span!("btree.range").entered();
+        check_range(&range);
+        Range { inner: Traversal::range(&*self.root, range.start_bound(), range.end_bound()) }
+    }
+
+    /// Like [`range`](Self::range), but with mutable values.
+    pub fn range_mut<Q, R>(&mut self, range: R) -> RangeMut<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let _span = tracing::trace_span!("btree.range_mut").entered();
+        check_range(&range);
+        RangeMut { inner: Traversal::range(&mut *self.root, range.start_bound(), range.end_bound()) }
+    }
+
+    /// The entry for `key`, to fill, update or remove in place with a single
+    /// lookup.
+    ///
+    /// ```
+    /// use btree::BTree;
+    ///
+    /// let mut counts = BTree::new(2);
+    /// for word in ["scrib", "guar", "scrib", "netch", "scrib"] {
+    ///     *counts.entry(word).or_insert(0) += 1;
+    /// }
+    /// assert_eq!(counts.get("scrib"), Some(&3));
+    /// ```
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        let _span = tracing::trace_span!("btree.entry").entered();
+        match cursor::search(&self.root, &key) {
+            Ok(path) => Entry::Occupied(OccupiedEntry { tree: self, path }),
+            Err(gap) => Entry::Vacant(VacantEntry { tree: self, key, gap }),
+        }
+    }
+
+    /// A cursor at the first entry whose key is at least `key`, or at the
+    /// ghost if there is none.
+    pub fn lower_bound<Q>(&self, key: &Q) -> Cursor<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::trace_span!("btree.lower_bound").entered();
+        Cursor { root: &self.root, current: cursor::seek(&self.root, key, false) }
+    }
+
+    /// A cursor at the last entry whose key is at most `key`, or at the
+    /// ghost if there is none.
+    pub fn upper_bound<Q>(&self, key: &Q) -> Cursor<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::trace_span!("btree.upper_bound").entered();
+        Cursor { root: &self.root, current: cursor::seek(&self.root, key, true) }
+    }
+
+    /// Like [`lower_bound`](Self::lower_bound), but the cursor can insert
+    /// and remove entries.
+    pub fn lower_bound_mut<Q>(&mut self, key: &Q) -> CursorMut<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::trace_span!("btree.lower_bound_mut").entered();
+        let current = cursor::seek(&self.root, key, false);
+        CursorMut { tree: self, current }
+    }
+
+    /// Like [`upper_bound`](Self::upper_bound), but the cursor can insert
+    /// and remove entries.
+    pub fn upper_bound_mut<Q>(&mut self, key: &Q) -> CursorMut<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::trace_span!("btree.upper_bound_mut").entered();
+        let current = cursor::seek(&self.root, key, true);
+        CursorMut { tree: self, current }
+    }
+
+    fn height(&self) -> usize {
+        self.root.height()
+    }
+
+    fn root_split(&mut self) -> usize {
+
+        let (median, right_child) = self.root.split();
+        let left_child = mem::replace(&mut *self.root, Node::new(&self.rules));
+        let sizes = if self.rules.counted { vec![left_child.size(), right_child.size()] } else { Vec::new() };
+        *self.root = Node::Internal(Internal {
+            items: vec![median],
+            children: vec![left_child, right_child],
+            sizes,
+        });
+
+        tracing::debug!(
+            height = self.height(),
+            left_items = self.root.children()[0].items().len(),
+            right_items = self.root.children()[1].items().len(),
+            "root split, tree grew a level",
+        );
+        // the old root keeps everything below the median
+        self.root.children()[0].items().len()
+    }
+
+    fn root_shrink(&mut self) -> bool {
+        // case 0: a merge under the root pulled its last key down, so the tree loses a level
+        if let Node::Internal(internal) = &mut *self.root
+            && internal.items.is_empty()
+        {
+            *self.root = internal.children.pop().unwrap();
+            tracing::debug!(
+                height = self.height(),
+                root_items = self.root.items().len(),
+                "root emptied, tree shrank a level",
+            );
+            return true;
+        }
+        false
+    }
+
+    /// Inserts `value` under `key`.
+    ///
+    /// If the key was already present its value is overwritten and the old
+    /// value returned; the stored key itself is left untouched.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let _span = tracing::debug_span!("btree.insert", len = self.len).entered();
+        if self.root.items().len() >= self.rules.maxkeys {
+            self.root_split();
+        }
+        let old = self.root.insert(Item { key, value }, &self.rules, 0);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// Removes `key` from the tree, returning its value if it was present.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+
+    /// Removes `key` from the tree, returning the stored key and its value if
+    /// it was present.
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::debug_span!("btree.remove", len = self.len).entered();
+        let removed = self.root.delete(key, &self.rules, 0);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        self.root_shrink();
+        removed.map(|item| (item.key, item.value))
+    }
+
+    /// Removes and returns the entry with the smallest key, if any.
+    ///
+    /// Walks the left spine making sure every node on the way down can spare
+    /// a key, just like [`remove`](Self::remove), so the tree stays balanced.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        let _span = tracing::debug_span!("btree.pop_first", len = self.len).entered();
+        if self.is_empty() {
+            return None;
+        }
+        let item = self.root.delete_min(&self.rules, 0);
+        self.len -= 1;
+        self.root_shrink();
+        Some((item.key, item.value))
+    }
+
+    /// Removes and returns the entry with the largest key, if any.
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        let _span = tracing::debug_span!("btree.pop_last", len = self.len).entered();
+        if self.is_empty() {
+            return None;
+        }
+        let item = self.root.delete_max(&self.rules, 0);
+        self.len -= 1;
+        self.root_shrink();
+        Some((item.key, item.value))
+    }
+
+    /// Returns an iterator that walks the tree in key order, removing and
+    /// yielding every entry for which `pred` returns `true`. The predicate
+    /// may also change the values of the entries it keeps.
+    ///
+    /// Each removal goes straight down the path the walk is on rather than
+    /// searching for the key, but still rebalances along it, so extracting
+    /// `k` entries costs `O(k log n)` on top of the walk. Entries the iterator
+    /// never reaches, because it was dropped early, are left alone. To drop
+    /// entries without being handed them, [`retain`](Self::retain) is cheaper.
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, K, V, F>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let span = tracing::debug_span!("btree.extract_if", len = self.len);
+        let current = cursor::next_of(&self.root, None);
+        ExtractIf { tree: self, current, pred, span }
+    }
+
+    /// Keeps only the entries for which `keep` returns `true`, removing the
+    /// rest.
+    ///
+    /// The entries are walked once, in key order, and those kept are bulk
+    /// loaded into a fresh tree of full nodes, so this is `O(n)` however many
+    /// go. If `keep` panics, the entry it was asked about and those it hadn't
+    /// got to yet all stay.
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let _span = tracing::debug_span!("btree.retain", len = self.len).entered();
+        let rest = self.take().into_iter();
+        let mut retained = Retained { kept: Vec::with_capacity(rest.len()), rest: rest.peekable(), tree: self };
+        // each entry leaves `rest` only once `keep` has had its say
+        while let Some((key, value)) = retained.rest.peek_mut() {
+            let kept = keep(key, value);
+            let (key, value) = retained.rest.next().unwrap();
+            if kept {
+                retained.kept.push(Item { key, value });
+            }
+        }
+    }
+
+    /// Walks the whole tree checking every B-Tree invariant, returning the
+    /// first violation found.
+    ///
+    /// Checked per node: keys strictly ascending and within the parent's
+    /// separators, key counts within the degree's bounds (the root may run
+    /// thin), internal nodes having one more child than keys, every leaf at the
+    /// same depth, and cached counters matching what they count. The tree's own
+    /// length is checked against the number of keys found.
+    pub fn validate(&self) -> Result<(), BTreeError> {
+        let _span = tracing::debug_span!("btree.validate", len = self.len).entered();
+        let actual = self.root.validate(&self.rules, (None, None), 0, &mut None, &mut Vec::new())?;
+        if self.len != actual {
+            return Err(BTreeError::Invalid {
+                path: Vec::new(),
+                violation: Violation::CounterDrift { counter: "len", cached: self.len, actual },
+            });
+        }
+        Ok(())
+    }
+
+    /// Like [`remove`](Self::remove), but treats a missing key as an error.
+    pub fn try_remove<Q>(&mut self, key: &Q) -> Result<V, BTreeError>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.remove(key).ok_or(BTreeError::KeyNotFound)
+    }
+}
+
+// what `retain` has kept so far and what it's still to look at, loaded back into the tree when
+// it's done, or if the predicate panics
+struct Retained<'a, K: Ord, V> {
+    tree: &'a mut BTree<K, V>,
+    kept: Vec<Item<K, V>>,
+    rest: Peekable<IntoIter<K, V>>,
+}
+
+impl<K: Ord, V> Drop for Retained<'_, K, V> {
+    fn drop(&mut self) {
+        let mut items = mem::take(&mut self.kept);
+        items.extend(self.rest.by_ref().map(|(key, value)| Item { key, value }));
+        let per = self.tree.rules.maxkeys;
+        self.tree.load(items, per);
+    }
+}
+
+fn check_range<Q, R>(range: &R)
+where
+    Q: Ord + ?Sized,
+    R: RangeBounds<Q>,
+{
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Excluded(start), Bound::Excluded(end)) if start == end => {
+            panic!("range start and end are equal and excluded")
+        }
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end) | Bound::Excluded(end))
+            if start > end =>
+        {
+            panic!("range start is greater than range end")
+        }
+        _ => {}
+    }
+}
+
+impl<K, V> BTree<K, V> {
+    /// Iterates over the entries in ascending key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: Traversal::new(&*self.root), length: self.len }
+    }
+
+    /// Iterates over the entries in ascending key order, with mutable values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut { inner: Traversal::new(&mut *self.root), length: self.len }
+    }
+
+    /// Iterates over the keys in ascending order.
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys { inner: self.iter() }
+    }
+
+    /// Iterates over the values in key order.
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values { inner: self.iter() }
+    }
+
+    /// Iterates over mutable references to the values in key order.
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut { inner: self.iter_mut() }
+    }
+}
+
+/// An empty tree of minimum degree 6.
+impl<K, V> Default for BTree<K, V>
+where
+    K: Ord,
+{
+    fn default() -> Self {
+        BTree::new(DEFAULT_DEGREE)
+    }
+}
+
+impl<K, V> IntoIterator for BTree<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    /// Consumes the tree, yielding its entries in ascending key order.
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter { inner: Traversal::new(*self.root), length: self.len }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a BTree<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a mut BTree<K, V> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        self.iter_mut()
+    }
+}
+
+/// Lays the tree out level by level, root first, one line of bracketed node
+/// keys per level, for eyeballing its shape.
+impl<K, V> Display for BTree<K, V>
+where
+    K: Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+
+        // travel the tree, filling up BTreeMap vec
+        let depth = 1;
+        let mut tree: BTreeMap<i32, Vec<String>> = BTreeMap::new();
+        let node = &self.root;
+
+        fn descend_printer<T: Display, E>(depth: i32, treemap: &mut BTreeMap<i32, Vec<String>>, node: &Node<T,E>) {
+            let next_items = node.items();
+            treemap.entry(depth).or_default().push(
+                format!("[{}]",
+                    next_items
+                        .iter()
+                        .map(|node| {
+                           format!("{}", node.key)
+                        }).collect::<Vec<String>>().join(",")
+                )
+            );
+            for node in node.children() {
+                descend_printer(depth+1, treemap, node);
+            }
+        }
+
+        // recursively load map
+        descend_printer(depth, &mut tree, node);
+
+        // gather formatting
+        let formatted: BTreeMap<i32, String> = tree
+            .into_iter()
+            .map(|(depth_, nodes)| {
+                let mut depth_space = "".to_string();
+                for _ in 0..10/depth_ {
+                    depth_space += "   ";
+                }
+                (
+                    depth_, nodes
+                                .into_iter()
+                                .collect::<Vec<String>>().join(&depth_space)
+                )
+            }).collect();
+
+        // write formatted btree
+        let max_depth = formatted.keys().last().unwrap();
+        let max_depth_len = formatted.get(max_depth).unwrap().len() as i32;
+        for (tree_depth, nodes_at_depth) in formatted.iter() {
+
+            let indent = max_depth_len / (tree_depth + (1));
+            let mut prefix_space = "".to_string();
+            for _ in 0..indent {
+                prefix_space += " ";
+            }
+            writeln!(f, "{prefix_space}{nodes_at_depth}")?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn setup_test_tree(degree: usize) -> BTree<i32, &'static str> {
+
+        let items = vec![
+            (7, "Daedric Bow"),
+            (23, "Nerevar's Ring"),
+            (67, "Vivec's Tears"),
+            (89, "Dwemer Cogwheel"),
+            (45, "Telvanni Bug Musk"),
+            (78, "Kagrenac's Tools"),
+            (34, "Moon Sugar"),
+            (91, "Almalexia's Grace"),
+            (56, "Cliff Racer Plume"),
+            (16, "Nerevarine's Gauntlet"),
+            (47, "Dunmer Ancestor Silk"),
+            (81, "Red Mountain Ash"),
+        ];
+
+        // btree
+        let mut btree = BTree::new(degree);
+
+        // insert
+        for (key, value) in items {
+            btree.insert(key, value);
+        }
+
+        btree
+    }
+
+    #[test]
+    fn find_key_simple() {
+
+        for degree in DEGREES {
+            let btree = setup_test_tree(degree);
+            assert_eq!(btree.degree(), degree);
+
+            assert_eq!(btree.get(&81), Some(&"Red Mountain Ash"));
+            assert_eq!(btree.get_key_value(&81), Some((&81, &"Red Mountain Ash")));
+            assert!(btree.contains_key(&81));
+            assert!(!btree.contains_key(&82));
+            assert_eq!(btree.get(&82), None);
+            assert_eq!(btree.len(), 12);
+            btree.validate().unwrap();
+        }
+    }
+    #[test]
+    fn delete_root() {
+
+        for degree in DEGREES {
+            let mut btree = setup_test_tree(degree);
+            let root_key = btree.root.items()[0].key;
+            assert!(btree.contains_key(&root_key));
+
+            btree.remove(&root_key);
+
+            assert!(!btree.contains_key(&root_key));
+            assert_eq!(btree.len(), 11);
+            for key in [7, 16, 23, 34, 45, 47, 56, 67, 78, 81, 89, 91] {
+                assert_eq!(btree.contains_key(&key), key != root_key);
+            }
+            btree.validate().unwrap();
+        }
+    }
+    #[test]
+    fn delete_root_shrinks_height() {
+
+        let mut btree = BTree::new(2);
+        for key in 1..=4 {
+            btree.insert(key, key * 10);
+        }
+        //      [2]
+        //     /   \
+        //   [1]  [3,4]
+        assert_eq!(btree.root.items().len(), 1);
+        assert_eq!(btree.root.children().len(), 2);
+
+        // borrows the successor from the right
+        btree.remove(&2);
+        assert_eq!(btree.root.items()[0].key, 3);
+
+        // both children at minimum: merge under the root, which empties it
+        btree.remove(&3);
+        assert!(matches!(*btree.root, Node::Leaf(_)));
+        assert_eq!(btree.root.items().iter().map(|item| item.key).collect::<Vec<_>>(), vec![1, 4]);
+        assert_eq!(btree.get(&4), Some(&40));
+
+        btree.remove(&1);
+        btree.remove(&4);
+        assert!(btree.is_empty());
+        assert!(matches!(*btree.root, Node::Leaf(_)));
+    }
+    #[test]
+    fn delete_everything() {
+
+        // 3000 keys is enough for a few levels even at degree 512
+        const N: i32 = 3000;
+        for degree in DEGREES {
+            let mut btree = BTree::new(degree);
+            // 37 and 53 are coprime with N, so these walk every key in a scrambled order
+            for i in 0..N {
+                btree.insert((i * 37) % N, i);
+            }
+            assert_eq!(btree.len(), N as usize);
+            btree.validate().unwrap();
+            for i in 0..N {
+                let key = (i * 53) % N;
+                btree.remove(&key);
+                assert!(!btree.contains_key(&key));
+                assert_eq!(btree.len(), (N - 1 - i) as usize);
+                if i % 250 == 0 {
+                    btree.validate().unwrap();
+                    for j in i + 1..N {
+                        assert!(btree.contains_key(&((j * 53) % N)));
+                    }
+                }
+            }
+            assert!(btree.is_empty());
+            assert!(matches!(*btree.root, Node::Leaf(_)));
+        }
+    }
+    #[test]
+    fn nodes_sized_by_bytes() {
+
+        let mut btree = BTree::with_rules(BTreeRules::for_node_size::<u32, u32>(1024));
+        assert_eq!(btree.degree(), 32);
+        for key in 0..5000u32 {
+            btree.insert(key, key);
+        }
+        btree.validate().unwrap();
+        assert_eq!(btree.height(), 3);
+    }
+    #[test]
+    fn displays_level_by_level() {
+
+        let mut btree = BTree::new(2);
+        for key in 1..=4 {
+            btree.insert(key, ());
+        }
+        let shown = btree.to_string();
+        let levels: Vec<&str> = shown.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+        assert_eq!(levels, ["[2]", "[1]               [3,4]"]);
+    }
+    #[test]
+    fn delete_internal() {
+
+        for degree in DEGREES {
+            let mut btree = setup_test_tree(degree);
+
+            assert!(btree.contains_key(&89));
+            assert_eq!(btree.remove(&89), Some("Dwemer Cogwheel"));
+
+            assert!(!btree.contains_key(&89));
+            assert_eq!(btree.get(&89), None);
+            assert_eq!(btree.len(), 11);
+            btree.validate().unwrap();
+        }
+    }
+    #[test]
+    fn delete_leaf() {
+
+        for degree in DEGREES {
+            let mut btree = setup_test_tree(degree);
+            assert!(btree.contains_key(&47));
+            assert_eq!(btree.remove_entry(&47), Some((47, "Dunmer Ancestor Silk")));
+
+            assert!(!btree.contains_key(&47));
+            btree.validate().unwrap();
+        }
+    }
+    #[test]
+    fn delete_leaf_at_minimum() {
+
+        for degree in DEGREES {
+            let mut btree = setup_test_tree(degree);
+            assert!(btree.contains_key(&34));
+            assert_eq!(btree.try_remove(&34), Ok("Moon Sugar"));
+
+            assert_eq!(btree.get(&34), None);
+            btree.validate().unwrap();
+        }
+    }
+    #[test]
+    fn get_mut_updates_value() {
+
+        for degree in DEGREES {
+            let mut btree = setup_test_tree(degree);
+            *btree.get_mut(&56).unwrap() = "Scrib Jelly";
+            assert_eq!(btree.get(&56), Some(&"Scrib Jelly"));
+            assert_eq!(btree.get_mut(&57), None);
+        }
+    }
+    #[test]
+    fn borrowed_key_lookup() {
+
+        for degree in DEGREES {
+            let mut btree = BTree::new(degree);
+            for name in ["Balmora", "Vivec", "Ald'ruhn", "Sadrith Mora", "Gnisis", "Ebonheart"] {
+                btree.insert(name.to_string(), name.len() as i32);
+            }
+            assert_eq!(btree.get("Vivec"), Some(&5));
+            assert!(btree.contains_key("Gnisis"));
+            assert!(!btree.contains_key("Seyda Neen"));
+
+            btree.remove("Gnisis");
+            assert!(!btree.contains_key("Gnisis"));
+            assert_eq!(btree.len(), 5);
+        }
+    }
+    #[test]
+    fn unconstrained_values() {
+
+        for degree in DEGREES {
+            // neither `Ord` nor `Clone`
+            let mut btree: BTree<i32, Vec<f64>> = BTree::new(degree);
+            for key in 0..20 {
+                btree.insert(key, vec![key as f64 * 0.5]);
+            }
+            btree.remove(&1);
+            assert_eq!(btree.get(&1), None);
+            assert_eq!(btree.get(&4), Some(&vec![2.0]));
+
+            let mut callbacks: BTree<&str, Box<dyn Fn(i32) -> i32>> = BTree::new(degree);
+            callbacks.insert("double", Box::new(|x| x * 2));
+            callbacks.insert("square", Box::new(|x| x * x));
+            assert_eq!(callbacks.get("square").unwrap()(7), 49);
+        }
+    }
+    #[test]
+    fn keys_need_only_ord() {
+
+        // no `Debug`, `Display` or `Clone`
+        #[derive(PartialEq, Eq, PartialOrd, Ord)]
+        struct Key(u32);
+
+        for degree in DEGREES {
+            let mut btree = BTree::new(degree);
+            for key in (0..100).rev() {
+                btree.insert(Key(key), key);
+            }
+            *btree.entry(Key(100)).or_insert(0) += 100;
+            btree.lower_bound_mut(&Key(100)).insert_before(Key(99), 0).unwrap_err();
+            btree.retain(|_, value| *value % 2 == 0);
+            btree.append(&mut BTree::from_iter([(Key(200), 200)]));
+            let high = btree.split_off(&Key(50));
+            assert_eq!(btree.len() + high.len(), 52);
+            assert_eq!(btree.remove(&Key(2)), Some(2));
+            assert_eq!(high.get(&Key(100)), Some(&100));
+        }
+    }
+    #[test]
+    fn remove_missing_key() {
+
+        for degree in DEGREES {
+            let mut btree = setup_test_tree(degree);
+            assert_eq!(btree.remove(&1000), None);
+            assert_eq!(btree.try_remove(&1000), Err(BTreeError::KeyNotFound));
+            assert_eq!(btree.len(), 12);
+            assert_eq!(btree.get(&91), Some(&"Almalexia's Grace"));
+        }
+    }
+    #[test]
+    fn insert_returns_previous_value() {
+
+        for degree in DEGREES {
+            let mut btree = setup_test_tree(degree);
+            assert_eq!(btree.insert(45, "Telvanni Bug Musk (fresh)"), Some("Telvanni Bug Musk"));
+            assert_eq!(btree.insert(46, "Scamp Skin"), None);
+            assert_eq!(btree.get(&45), Some(&"Telvanni Bug Musk (fresh)"));
+            assert_eq!(btree.len(), 13);
+        }
+    }
+    #[test]
+    fn overwrite_every_key() {
+
+        // overwrites land on keys sitting anywhere, including the median of a child
+        // that gets split on the way down
+        for degree in DEGREES {
+            let mut btree = BTree::new(degree);
+            for key in 0..1000 {
+                assert_eq!(btree.insert(key, key), None);
+            }
+            for key in (0..1000).rev() {
+                assert_eq!(btree.insert(key, key + 1), Some(key));
+            }
+            assert_eq!(btree.len(), 1000);
+            btree.validate().unwrap();
+            for key in 0..1000 {
+                assert_eq!(btree.remove(&key), Some(key + 1));
+            }
+            assert!(btree.is_empty());
+        }
+    }
+    // collects span names and event messages so we can see what the tree reported
+    #[derive(Clone, Default)]
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl tracing::Subscriber for Recorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            self.0.lock().unwrap().push(span.metadata().name().to_string());
+            tracing::span::Id::from_u64(1)
+        }
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+        fn event(&self, event: &tracing::Event<'_>) {
+            struct Message<'a>(&'a mut Vec<String>);
+            impl tracing::field::Visit for Message<'_> {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn Debug) {
+                    if field.name() == "message" {
+                        self.0.push(format!("{value:?}"));
+                    }
+                }
+            }
+            event.record(&mut Message(&mut self.0.lock().unwrap()));
+        }
+        fn enter(&self, _: &tracing::span::Id) {}
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[test]
+    fn restructuring_is_traced() {
+
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let mut btree = BTree::new(2);
+            for key in 0..10 {
+                btree.insert(key, ());
+            }
+            for key in 0..10 {
+                btree.remove(&key);
+            }
+            btree.extend((0..10).map(|key| (key, ())));
+            btree.extract_if(|key, _| key % 3 != 0).for_each(drop);
+        });
+
+        let log = recorder.0.lock().unwrap();
+        for expected in [
+            "btree.insert",
+            "btree.remove",
+            "btree.extract_if",
+            "root split, tree grew a level",
+            "split full child",
+            "rotated key from sibling",
+            "merged siblings",
+            "root emptied, tree shrank a level",
+        ] {
+            assert!(log.iter().any(|line| line == expected), "no {expected:?} in {log:?}");
+        }
+    }
+    #[test]
+    fn validate_reports_violations() {
+
+        fn items(keys: &[i32]) -> Vec<Item<i32, ()>> {
+            keys.iter().map(|&key| Item { key, value: () }).collect()
+        }
+        fn leaf(keys: &[i32]) -> Node<i32, ()> {
+            Node::Leaf(node::Leaf { items: items(keys) })
+        }
+        fn internal(keys: &[i32], children: Vec<Node<i32, ()>>) -> Node<i32, ()> {
+            Node::Internal(Internal { items: items(keys), children, sizes: Vec::new() })
+        }
+        fn check(root: Node<i32, ()>, len: usize) -> Result<(), BTreeError> {
+            let mut btree = BTree::new(2);
+            *btree.root = root;
+            btree.len = len;
+            btree.validate()
+        }
+        fn violation(path: &[usize], violation: Violation) -> Result<(), BTreeError> {
+            Err(BTreeError::Invalid { path: path.to_vec(), violation })
+        }
+
+        assert_eq!(check(internal(&[10], vec![leaf(&[5]), leaf(&[15])]), 3), Ok(()));
+        assert_eq!(
+            check(internal(&[10], vec![leaf(&[5, 4]), leaf(&[15])]), 4),
+            violation(&[0], Violation::UnsortedKeys { index: 1 }),
+        );
+        assert_eq!(
+            check(internal(&[10], vec![leaf(&[5]), leaf(&[9])]), 3),
+            violation(&[1], Violation::KeyOutOfBounds { index: 0 }),
+        );
+        assert_eq!(
+            check(internal(&[10], vec![leaf(&[]), leaf(&[15])]), 2),
+            violation(&[0], Violation::TooFewKeys { keys: 0, min: 1 }),
+        );
+        assert_eq!(
+            check(leaf(&[1, 2, 3, 4]), 4),
+            violation(&[], Violation::TooManyKeys { keys: 4, max: 3 }),
+        );
+        assert_eq!(
+            check(internal(&[10, 20], vec![leaf(&[5]), leaf(&[15])]), 4),
+            violation(&[], Violation::ChildCount { keys: 2, children: 2 }),
+        );
+        assert_eq!(
+            check(internal(&[10], vec![leaf(&[5]), internal(&[20], vec![leaf(&[15]), leaf(&[25])])]), 5),
+            violation(&[1, 0], Violation::UnevenLeaves { depth: 2, expected: 1 }),
+        );
+
+        assert_eq!(
+            check(leaf(&[1, 2]), 5),
+            violation(&[], Violation::CounterDrift { counter: "len", cached: 5, actual: 2 }),
+        );
+    }
+    #[test]
+    fn iterates_in_key_order() {
+
+        let sorted = [7, 16, 23, 34, 45, 47, 56, 67, 78, 81, 89, 91];
+        for degree in DEGREES {
+            let btree = setup_test_tree(degree);
+            assert!(btree.iter().map(|(key, _)| *key).eq(sorted));
+            assert!(btree.keys().rev().copied().eq(sorted.into_iter().rev()));
+            assert_eq!(btree.values().next(), Some(&"Daedric Bow"));
+            assert_eq!(btree.values().next_back(), Some(&"Almalexia's Grace"));
+            assert_eq!(btree.iter().len(), 12);
+            assert_eq!((&btree).into_iter().count(), 12);
+        }
+    }
+    #[test]
+    fn iterates_from_both_ends() {
+
+        const N: i32 = 1000;
+        for degree in DEGREES {
+            let mut btree = BTree::new(degree);
+            for key in (0..N).map(|i| (i * 37) % N) {
+                btree.insert(key, ());
+            }
+
+            // take unevenly from each end until they meet somewhere in the middle of a node
+            for split in [0, 1, 499, 500, 777, 999, 1000] {
+                let mut iter = btree.keys();
+                let front: Vec<i32> = iter.by_ref().take(split).copied().collect();
+                let back: Vec<i32> = iter.by_ref().rev().copied().collect();
+                assert!(front.iter().copied().eq(0..split as i32));
+                assert!(back.iter().copied().eq((split as i32..N).rev()));
+                assert_eq!(iter.next(), None);
+                assert_eq!(iter.next_back(), None);
+            }
+
+            let mut iter = btree.keys();
+            let mut seen = Vec::new();
+            while let (Some(&low), Some(&high)) = (iter.next(), iter.next_back()) {
+                seen.extend([low, high]);
+                assert_eq!(iter.len(), (N - 2 * (low + 1)) as usize);
+            }
+            seen.sort();
+            assert!(seen.into_iter().eq(0..N));
+        }
+    }
+    #[test]
+    fn iter_mut_updates_values() {
+
+        for degree in DEGREES {
+            let mut btree = setup_test_tree(degree);
+            for (key, value) in btree.iter_mut() {
+                *value = if key % 2 == 0 { "even" } else { "odd" };
+            }
+            for value in btree.values_mut().rev().take(1) {
+                *value = "last";
+            }
+            assert_eq!(btree.get(&16), Some(&"even"));
+            assert_eq!(btree.get(&7), Some(&"odd"));
+            assert_eq!(btree.get(&91), Some(&"last"));
+            for (_, value) in &mut btree {
+                assert_ne!(*value, "Daedric Bow");
+            }
+            btree.validate().unwrap();
+        }
+    }
+    #[test]
+    fn into_iter_yields_owned_entries() {
+
+        for degree in DEGREES {
+            let mut btree: BTree<i32, Vec<f64>> = BTree::new(degree);
+            for key in (0..200).rev() {
+                btree.insert(key, vec![key as f64]);
+            }
+            let mut entries = btree.into_iter();
+            assert_eq!(entries.len(), 200);
+            assert_eq!(entries.next(), Some((0, vec![0.0])));
+            assert_eq!(entries.next_back(), Some((199, vec![199.0])));
+            assert!(entries.map(|(key, value)| key as f64 - value[0]).all(|diff| diff == 0.0));
+
+            assert_eq!(BTree::<i32, ()>::new(degree).into_iter().next(), None);
+        }
+    }
+    #[test]
+    fn range_matches_std() {
+
+        // every third key is present, so bounds land both on and between keys
+        let bounds = |key: i32| [Bound::Included(key), Bound::Excluded(key), Bound::Unbounded];
+        for degree in DEGREES {
+            let mut btree = BTree::new(degree);
+            let mut expected = BTreeMap::new();
+            for key in (0..150).map(|i| (i * 37) % 150 * 3) {
+                btree.insert(key, key * 10);
+                expected.insert(key, key * 10);
+            }
+
+            for start in (-2..455).step_by(7) {
+                for end in (start..458).step_by(11) {
+                    for range in bounds(start).into_iter().flat_map(|low| bounds(end).map(|high| (low, high))) {
+                        if let (Bound::Excluded(low), Bound::Excluded(high)) = range && low == high {
+                            continue;
+                        }
+                        assert!(btree.range(range).eq(expected.range(range)), "{range:?} at degree {degree}");
+                        assert!(btree.range(range).rev().eq(expected.range(range).rev()), "{range:?} at degree {degree}");
+
+                        // meet in the middle from both ends
+                        let mut ours = btree.range(range);
+                        let mut theirs = expected.range(range);
+                        loop {
+                            let (front, back) = (ours.next(), ours.next_back());
+                            assert_eq!((front, back), (theirs.next(), theirs.next_back()));
+                            if back.is_none() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    #[test]
+    fn range_forms() {
+
+        for degree in DEGREES {
+            let btree = setup_test_tree(degree);
+            let keys = |range: Range<i32, &str>| range.map(|(key, _)| *key).collect::<Vec<_>>();
+            assert_eq!(keys(btree.range(34..56)), [34, 45, 47]);
+            assert_eq!(keys(btree.range(34..=56)), [34, 45, 47, 56]);
+            assert_eq!(keys(btree.range(80..)), [81, 89, 91]);
+            assert_eq!(keys(btree.range(..16)), [7]);
+            assert_eq!(keys(btree.range(..)).len(), 12);
+            assert_eq!(keys(btree.range((Bound::Excluded(7), Bound::Included(23)))), [16, 23]);
+            assert_eq!(keys(btree.range(50..50)), []);
+            assert_eq!(keys(btree.range(92..)), []);
+        }
+
+        let mut names = BTree::new(2);
+        for name in ["Balmora", "Vivec", "Ald'ruhn", "Sadrith Mora", "Gnisis", "Ebonheart"] {
+            names.insert(name.to_string(), ());
+        }
+        let towns: Vec<&String> = names.range::<str, _>((Bound::Included("B"), Bound::Excluded("S"))).map(|(town, _)| town).collect();
+        assert_eq!(towns, ["Balmora", "Ebonheart", "Gnisis"]);
+    }
+    #[test]
+    fn range_mut_updates_only_the_range() {
+
+        for degree in DEGREES {
+            let mut btree = setup_test_tree(degree);
+            for (_, value) in btree.range_mut(45..=78) {
+                *value = "sold";
+            }
+            let sold: Vec<i32> = btree.iter().filter(|(_, value)| **value == "sold").map(|(key, _)| *key).collect();
+            assert_eq!(sold, [45, 47, 56, 67, 78]);
+            assert_eq!(btree.range_mut(..).next_back(), Some((&91, &mut "Almalexia's Grace")));
+            btree.validate().unwrap();
+        }
+    }
+    #[test]
+    #[should_panic(expected = "range start is greater than range end")]
+    fn range_backwards() {
+        setup_test_tree(2).range((Bound::Included(60), Bound::Excluded(50)));
+    }
+    #[test]
+    fn first_and_last() {
+
+        for degree in DEGREES {
+            let btree = setup_test_tree(degree);
+            assert_eq!(btree.first_key_value(), Some((&7, &"Daedric Bow")));
+            assert_eq!(btree.last_key_value(), Some((&91, &"Almalexia's Grace")));
+        }
+        let empty = BTree::<i32, ()>::new(2);
+        assert_eq!((empty.first_key_value(), empty.last_key_value()), (None, None));
+    }
+    #[test]
+    fn pops_drain_like_a_queue() {
+
+        const N: i32 = 1000;
+        for degree in DEGREES {
+            let mut btree = BTree::new(degree);
+            for key in (0..N).map(|i| (i * 37) % N) {
+                btree.insert(key, key * 2);
+            }
+
+            // alternate ends so both spines keep getting rebalanced
+            for i in 0..N / 2 {
+                assert_eq!(btree.pop_first(), Some((i, i * 2)));
+                assert_eq!(btree.pop_last(), Some((N - 1 - i, (N - 1 - i) * 2)));
+                if i % 50 == 0 {
+                    btree.validate().unwrap();
+                }
+                assert_eq!(btree.first_key_value().map(|(key, _)| *key), (i + 1 < N / 2).then_some(i + 1));
+            }
+            assert!(btree.is_empty());
+            assert_eq!(btree.pop_first(), None);
+            assert_eq!(btree.pop_last(), None);
+            btree.validate().unwrap();
+        }
+    }
+    #[test]
+    fn retain_matches_std() {
+
+        for degree in DEGREES {
+            for mut btree in [BTree::new(degree), BTree::with_subtree_sizes(degree)] {
+                let mut expected = BTreeMap::new();
+                for key in (0..2000).map(|i| (i * 37) % 2000) {
+                    btree.insert(key, key);
+                    expected.insert(key, key);
+                }
+
+                // drop a contiguous run, a scattered few and update the survivors on the way
+                let keep = |key: &i32, value: &mut i32| {
+                    *value += 1;
+                    !(500..900).contains(key) && key % 7 != 0
+                };
+                btree.retain(keep);
+                expected.retain(keep);
+                btree.validate().unwrap();
+                assert!(btree.iter().eq(expected.iter()));
+
+                btree.retain(|_, _| false);
+                assert!(btree.is_empty());
+                btree.validate().unwrap();
+            }
+        }
+    }
+    #[test]
+    fn a_panicking_retain_keeps_what_it_had_not_reached() {
+
+        let mut btree: BTree<i32, i32> = (0..100).map(|key| (key, key)).collect();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            btree.retain(|key, _| {
+                assert!(*key < 50, "gave up at {key}");
+                key % 2 == 0
+            })
+        }));
+        assert!(panicked.is_err());
+        btree.validate().unwrap();
+        assert!(btree.keys().copied().eq((0..50).step_by(2).chain(50..100)));
+        assert_eq!(btree.len(), 75);
+    }
+    #[test]
+    fn subtree_sizes_survive_every_mutation() {
+
+        for degree in DEGREES {
+            let mut btree = BTree::with_subtree_sizes(degree);
+            let mut expected = BTreeMap::new();
+            for i in 0..3000 {
+                let key = (i * 37) % 1009;
+                match i % 7 {
+                    0..=2 => {
+                        btree.insert(key, i);
+                        expected.insert(key, i);
+                    }
+                    3 => {
+                        *btree.entry(key).or_insert(0) += 1;
+                        *expected.entry(key).or_insert(0) += 1;
+                    }
+                    4 => assert_eq!(btree.remove(&key), expected.remove(&key)),
+                    5 => assert_eq!(btree.pop_first(), expected.pop_first()),
+                    _ => {
+                        let mut cursor = btree.lower_bound_mut(&key);
+                        if cursor.key().is_some() {
+                            let (key, value) = cursor.remove_current().unwrap();
+                            assert_eq!(expected.remove(&key), Some(value));
+                        }
+                    }
+                }
+                if i % 100 == 0 {
+                    btree.validate().unwrap();
+                }
+            }
+            btree.validate().unwrap();
+            assert!(btree.iter().eq(expected.iter()));
+        }
+    }
+    #[test]
+    fn order_statistics() {
+
+        for degree in DEGREES {
+            // the same answers whether they come from subtree sizes or from walking the entries
+            for mut btree in [BTree::with_subtree_sizes(degree), BTree::new(degree)] {
+                for key in (0..500).map(|i| (i * 37) % 500 * 2) {
+                    btree.insert(key, ());
+                }
+                for index in 0..501 {
+                    assert_eq!(btree.nth(index).map(|(key, _)| *key), (index < 500).then_some(index as i32 * 2));
+                }
+                for key in -1..1001 {
+                    let below = (key + 1).clamp(0, 1000) as usize / 2;
+                    assert_eq!(btree.rank(&key), below, "rank({key}) at degree {degree}");
+                    assert_eq!(btree.count_range(key..), 500 - below);
+                    assert_eq!(btree.count_range(..=key), btree.range(..=key).count());
+                    assert_eq!(btree.count_range(key..key + 100), btree.range(key..key + 100).count());
+                    assert_eq!(
+                        btree.count_range((Bound::Excluded(key), Bound::Excluded(key + 7))),
+                        btree.range((Bound::Excluded(key), Bound::Excluded(key + 7))).count(),
+                    );
+                }
+                assert_eq!(btree.count_range(..), 500);
+            }
+        }
+    }
+    #[test]
+    fn validate_reports_size_drift() {
+
+        let mut btree = BTree::with_subtree_sizes(2);
+        for key in 0..10 {
+            btree.insert(key, ());
+        }
+        btree.validate().unwrap();
+        let Node::Internal(root) = &mut *btree.root else { panic!("ten keys fit in no degree 2 leaf") };
+        root.sizes[0] += 1;
+        let actual = root.sizes[0] - 1;
+        assert_eq!(
+            btree.validate(),
+            Err(BTreeError::Invalid {
+                path: vec![],
+                violation: Violation::CounterDrift { counter: "subtree size", cached: actual + 1, actual },
+            }),
+        );
+    }
+    #[test]
+    #[should_panic]
+    fn degree_below_two() {
+        BTree::<i32, ()>::new(1);
+    }
+}
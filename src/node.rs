@@ -0,0 +1,921 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::mem;
+
+use crate::codec::Codec;
+use crate::disk::NODE_HEADER;
+use crate::error::{BTreeError, Violation};
+use crate::pager::PageId;
+
+/// How many keys the nodes of a [`BTree`](crate::BTree) hold: from a minimum
+/// degree `t`, between `t - 1` and `2t - 1` each (the root may hold fewer),
+/// and one more child than keys.
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Copy, Clone)]
+pub struct BTreeRules {
+    pub(crate) maxkeys: usize,
+    pub(crate) maxchildren: usize,
+    pub(crate) minkeys: usize,
+    pub(crate) minchildren: usize,
+    pub(crate) degree: usize,
+    // whether internal nodes keep the size of every child's subtree, for order statistics
+    pub(crate) counted: bool,
+}
+impl BTreeRules {
+    // t = branching factor, where t >= 2
+    // node must have at least t-1 keys (and t children  if not a leaf)
+    // node can  have at most 2t-1 keys (and 2t children if not a leaf)
+    /// The rules for minimum degree `degree`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `degree` is less than 2.
+    pub fn new(degree: usize) -> Self {
+        assert!(degree >= 2, "a B-Tree needs a degree of at least 2, got {degree}");
+        BTreeRules {
+            // For inserts
+            maxkeys: (2 * degree) - 1,
+            maxchildren: 2 * degree,
+            // For deletes
+            minkeys: degree - 1,
+            minchildren: degree,
+            degree,
+            counted: false,
+        }
+    }
+
+    /// The rules with the largest degree whose full node still fits in
+    /// `node_bytes`, laid out the way a [`DiskBTree`](crate::DiskBTree) lays
+    /// out a page: a 3-byte header, an 8-byte id per child, and every entry
+    /// taking the [`WIDTH`](Codec::WIDTH)s of `K` and `V`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `K` or `V` has no fixed width, or if `node_bytes` is too
+    /// small for a node of degree 2.
+    pub fn for_node_size<K: Codec, V: Codec>(node_bytes: usize) -> Self {
+        let (Some(key), Some(value)) = (K::WIDTH, V::WIDTH) else {
+            panic!("sizing nodes by bytes needs keys and values of a fixed width");
+        };
+        BTreeRules::fitting(node_bytes, NODE_HEADER, key + value, size_of::<PageId>())
+    }
+    // the biggest degree whose full node fits in `node_bytes`: a fixed `header`, then `maxkeys`
+    // entries and `maxchildren` child pointers of the given widths
+    fn fitting(node_bytes: usize, header: usize, entry: usize, child: usize) -> Self {
+        let degree = (node_bytes.saturating_sub(header) + entry) / (2 * (entry + child));
+        assert!(
+            degree >= 2,
+            "{node_bytes}-byte nodes can't hold three {entry}-byte entries, too small for any B-Tree",
+        );
+        BTreeRules::new(degree)
+    }
+
+    /// The minimum degree.
+    pub fn degree(&self) -> usize {
+        self.degree
+    }
+
+    /// The most keys a node holds.
+    pub fn max_keys(&self) -> usize {
+        self.maxkeys
+    }
+
+    /// The fewest keys a node other than the root holds.
+    pub fn min_keys(&self) -> usize {
+        self.minkeys
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Item<K, V> {
+    pub(crate) key: K,
+    pub(crate) value: V,
+}
+
+// leaves only ever hold items, so they don't pay for an (always empty) children array
+#[derive(Debug, Clone)]
+pub(crate) struct Leaf<K, V> {
+    pub(crate) items: Vec<Item<K, V>>,
+}
+
+// internal nodes always hold exactly one more child than items. in a counted tree `sizes` holds
+// the number of items under each child, otherwise it stays empty
+#[derive(Debug, Clone)]
+pub(crate) struct Internal<K, V> {
+    pub(crate) items: Vec<Item<K, V>>,
+    pub(crate) children: Vec<Node<K, V>>,
+    pub(crate) sizes: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Node<K, V> {
+    Leaf(Leaf<K, V>),
+    Internal(Internal<K, V>),
+}
+
+impl<K, V> Leaf<K, V> {
+    // the tree owns the one `BTreeRules`, node operations that care about sizing borrow it
+    pub(crate) fn new(rules: &BTreeRules) -> Self {
+        Leaf {
+            items: Vec::with_capacity(rules.maxkeys),
+        }
+    }
+    fn split(&mut self) -> (Item<K, V>, Leaf<K, V>) {
+        // -- split the items, the median goes up to the parent
+        let median = self.items.len() / 2;
+        let items = self.items.split_off(median + 1);
+        let median_item = self.items.pop().unwrap();
+        (median_item, Leaf { items })
+    }
+}
+
+impl<K, V> Internal<K, V> {
+    fn split(&mut self) -> (Item<K, V>, Internal<K, V>) {
+        // -- split the items, the median goes up to the parent
+        let median = self.items.len() / 2;
+        let items = self.items.split_off(median + 1);
+        let median_item = self.items.pop().unwrap();
+        // -- split the children, each side keeping one more than its items
+        let children = self.children.split_off(median + 1);
+        let sizes = if self.counted() { self.sizes.split_off(median + 1) } else { Vec::new() };
+        (median_item, Internal { items, children, sizes })
+    }
+    pub(crate) fn counted(&self) -> bool {
+        !self.sizes.is_empty()
+    }
+    fn recount(&mut self, position: usize) {
+        // after children change shape, measure rather than track what moved where
+        if self.counted() {
+            self.sizes[position] = self.children[position].size();
+        }
+    }
+    fn grew(&mut self, position: usize) {
+        if self.counted() {
+            self.sizes[position] += 1;
+        }
+    }
+    fn shrank(&mut self, position: usize) {
+        if self.counted() {
+            self.sizes[position] -= 1;
+        }
+    }
+}
+
+// shape accessors: these don't care about key order, so they carry no bounds
+impl<K, V> Node<K, V> {
+    pub(crate) fn new(rules: &BTreeRules) -> Self {
+        Node::Leaf(Leaf::new(rules))
+    }
+    pub(crate) fn items(&self) -> &[Item<K, V>] {
+        match self {
+            Node::Leaf(leaf) => &leaf.items,
+            Node::Internal(internal) => &internal.items,
+        }
+    }
+    pub(crate) fn items_mut(&mut self) -> &mut Vec<Item<K, V>> {
+        match self {
+            Node::Leaf(leaf) => &mut leaf.items,
+            Node::Internal(internal) => &mut internal.items,
+        }
+    }
+    pub(crate) fn children(&self) -> &[Node<K, V>] {
+        match self {
+            Node::Leaf(_) => &[],
+            Node::Internal(internal) => &internal.children,
+        }
+    }
+    pub(crate) fn size(&self) -> usize {
+        // items in this whole subtree, only meaningful in a counted tree
+        match self {
+            Node::Leaf(leaf) => leaf.items.len(),
+            Node::Internal(internal) => internal.items.len() + internal.sizes.iter().sum::<usize>(),
+        }
+    }
+    pub(crate) fn height(&self) -> usize {
+        // every leaf sits at the same depth, so the left spine is as good as any
+        let mut height = 1;
+        let mut node = self;
+        while let Node::Internal(internal) = node {
+            node = &internal.children[0];
+            height += 1;
+        }
+        height
+    }
+    fn insertable(&self, rules: &BTreeRules) -> bool {
+        self.items().len() < rules.maxkeys
+    }
+    fn enough(&self, rules: &BTreeRules) -> bool {
+        self.items().len() >= rules.degree
+    }
+    pub(crate) fn split(&mut self) -> (Item<K, V>, Node<K, V>) {
+        match self {
+            Node::Leaf(leaf) => {
+                let (median, right) = leaf.split();
+                (median, Node::Leaf(right))
+            }
+            Node::Internal(internal) => {
+                let (median, right) = internal.split();
+                (median, Node::Internal(right))
+            }
+        }
+    }
+
+    // -- rotation halves: a lender gives up an edge item (and the child pointer beside it, if
+    //    internal), and the borrower takes both in on the opposite edge.
+    fn pop_first(&mut self) -> (Item<K, V>, Option<Node<K, V>>) {
+        match self {
+            Node::Leaf(leaf) => (leaf.items.remove(0), None),
+            Node::Internal(internal) => {
+                if internal.counted() {
+                    internal.sizes.remove(0);
+                }
+                (internal.items.remove(0), Some(internal.children.remove(0)))
+            }
+        }
+    }
+    fn pop_last(&mut self) -> (Item<K, V>, Option<Node<K, V>>) {
+        match self {
+            Node::Leaf(leaf) => (leaf.items.pop().unwrap(), None),
+            Node::Internal(internal) => {
+                internal.sizes.pop();
+                (internal.items.pop().unwrap(), internal.children.pop())
+            }
+        }
+    }
+    fn push_first(&mut self, item: Item<K, V>, child: Option<Node<K, V>>) {
+        match (self, child) {
+            (Node::Leaf(leaf), None) => leaf.items.insert(0, item),
+            (Node::Internal(internal), Some(child)) => {
+                if internal.counted() {
+                    internal.sizes.insert(0, child.size());
+                }
+                internal.items.insert(0, item);
+                internal.children.insert(0, child);
+            }
+            _ => unreachable!("rotating between nodes at different heights"),
+        }
+    }
+    fn push_last(&mut self, item: Item<K, V>, child: Option<Node<K, V>>) {
+        match (self, child) {
+            (Node::Leaf(leaf), None) => leaf.items.push(item),
+            (Node::Internal(internal), Some(child)) => {
+                if internal.counted() {
+                    internal.sizes.push(child.size());
+                }
+                internal.items.push(item);
+                internal.children.push(child);
+            }
+            _ => unreachable!("rotating between nodes at different heights"),
+        }
+    }
+    pub(crate) fn absorb(&mut self, separator: Item<K, V>, right: Node<K, V>) {
+        // -- merging: left ++ separator ++ right, children following along if internal
+        match (self, right) {
+            (Node::Leaf(left), Node::Leaf(right)) => {
+                left.items.push(separator);
+                left.items.extend(right.items);
+            }
+            (Node::Internal(left), Node::Internal(right)) => {
+                left.items.push(separator);
+                left.items.extend(right.items);
+                left.children.extend(right.children);
+                left.sizes.extend(right.sizes);
+            }
+            _ => unreachable!("merging nodes at different heights"),
+        }
+    }
+}
+
+impl<K, V> Node<K, V>
+where
+    K: Ord,
+{
+    pub(crate) fn binary_search<Q>(&self, key: &Q) -> (usize, bool)
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        // If key is GT Node.`items` array, return index + 1 than bounds of array
+        // If key is LT Node.`items` array, return 0.
+        // `true` means index returned is interpereted as the key in Node.`items.keys`
+        // `false` is an index into Node.`children` array
+        let items = self.items();
+        let mut low = 0;
+        let mut high = items.len();
+        while low < high {
+            let median = (low + high) / 2;
+            match key.cmp(items[median].key.borrow()) {
+                Ordering::Less => {
+                    high = median;
+                }
+                Ordering::Equal => return (median, true),
+                Ordering::Greater => {
+                    low = median + 1;
+                }
+            }
+        }
+        (low, false)
+    }
+    pub(crate) fn get<Q>(&self, key: &Q) -> Option<&Item<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        // walk down from here: either we land on the key, or `position` is the child to try next
+        let mut node = self;
+        loop {
+            let (position, found) = node.binary_search(key);
+            if found {
+                return Some(&node.items()[position]);
+            }
+            match node {
+                Node::Leaf(_) => return None,
+                Node::Internal(internal) => node = &internal.children[position],
+            }
+        }
+    }
+    pub(crate) fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut Item<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut node = self;
+        loop {
+            let (position, found) = node.binary_search(key);
+            if found {
+                return Some(&mut node.items_mut()[position]);
+            }
+            match node {
+                Node::Leaf(_) => return None,
+                Node::Internal(internal) => node = &mut internal.children[position],
+            }
+        }
+    }
+    pub(crate) fn delete<Q>(&mut self, key: &Q, rules: &BTreeRules, depth: usize) -> Option<Item<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+
+        // - Do not descend unless enough keys
+        // - KTD = key to delete
+        // - hands back the removed item, or `None` if the KTD was never here
+
+        // A1.i. look for item to delete
+        let (position, found) = self.binary_search(key);
+
+        match self {
+            // A1.ii. base case: bottomed out, it's either here (plain old goodbye) or nowhere
+            Node::Leaf(leaf) => found.then(|| leaf.items.remove(position)),
+            Node::Internal(internal) => internal.delete(key, position, found, rules, depth),
+        }
+    }
+    pub(crate) fn delete_max(&mut self, rules: &BTreeRules, depth: usize) -> Item<K, V> {
+        // the deepest, biggest key below us, making enough on the way down the right spine
+        match self {
+            Node::Leaf(leaf) => leaf.items.pop().unwrap(),
+            Node::Internal(internal) => {
+                let (position, _) = internal.make_enough(internal.children.len() - 1, rules, depth);
+                internal.shrank(position);
+                internal.children[position].delete_max(rules, depth + 1)
+            }
+        }
+    }
+    pub(crate) fn delete_min(&mut self, rules: &BTreeRules, depth: usize) -> Item<K, V> {
+        // the deepest, smallest key below us, making enough on the way down the left spine
+        match self {
+            Node::Leaf(leaf) => leaf.items.remove(0),
+            Node::Internal(internal) => {
+                let (position, _) = internal.make_enough(0, rules, depth);
+                internal.shrank(position);
+                internal.children[position].delete_min(rules, depth + 1)
+            }
+        }
+    }
+    pub(crate) fn insert(&mut self, item: Item<K, V>, rules: &BTreeRules, depth: usize) -> Option<V> {
+        // splitting echoes throughout the tree. we try to be proactive, splitting-while-visit
+        // in one downward pass. we insert and leave, meaning we don't check if the insertion triggers a split.
+        // we deal with that as the next insert's problem.
+        // thus, if the current node in the `insert` call is root, we'll deal with it's split in the `BTree.insert()` call.
+
+        // case 1: found item in node, overwrite the value and hand back the old one
+        let (position, found) = self.binary_search(&item.key);
+        if found {
+            return Some(mem::replace(&mut self.items_mut()[position].value, item.value));
+        }
+        match self {
+            // case 2: you're at a leaf, and our parent made sure it has capacity
+            Node::Leaf(leaf) => {
+                debug_assert!(leaf.items.len() < rules.maxkeys);
+                leaf.items.insert(position, item);
+                None
+            }
+            Node::Internal(internal) => internal.insert(item, position, rules, depth),
+        }
+    }
+    pub(crate) fn make_room(&mut self, gap: &mut [usize], rules: &BTreeRules, depth: usize) {
+        // the same proactive splitting as `insert`, steered by a path instead of a key: `gap` holds
+        // a child index per level and then an edge in the leaf, and is kept pointing at the same
+        // spot as full children split in half under it. the caller always fills the gap, so it's
+        // counted in on the way down
+        if let Node::Internal(internal) = self {
+            let (position, rest) = gap.split_first_mut().unwrap();
+            if internal.splittable_child(*position, rules) {
+                let median = internal.split_child(*position, depth);
+                if rest[0] > median {
+                    *position += 1;
+                    rest[0] -= median + 1;
+                }
+            }
+            internal.grew(*position);
+            internal.children[*position].make_room(rest, rules, depth + 1);
+        }
+    }
+    pub(crate) fn delete_at(&mut self, path: &mut [usize], rules: &BTreeRules, depth: usize) -> Item<K, V> {
+        // the same proactive descent as `delete`, steered by a path to a leaf item, which is
+        // patched up as make_enough pulls keys in from the left
+        match self {
+            Node::Leaf(leaf) => leaf.items.remove(path[0]),
+            Node::Internal(internal) => {
+                let (position, rest) = path.split_first_mut().unwrap();
+                let (moved, shift) = internal.make_enough(*position, rules, depth);
+                *position = moved;
+                rest[0] += shift;
+                internal.shrank(moved);
+                internal.children[moved].delete_at(rest, rules, depth + 1)
+            }
+        }
+    }
+    pub(crate) fn validate(
+        &self,
+        rules: &BTreeRules,
+        bounds: (Option<&K>, Option<&K>),
+        depth: usize,
+        leaf_depth: &mut Option<usize>,
+        path: &mut Vec<usize>,
+    ) -> Result<usize, BTreeError> {
+        // checks this node, then its children left to right, stopping at the first thing wrong.
+        // `bounds` are the parent's separators either side of us, `None` at the tree's edges.
+        // hands back how many items the subtree holds, to check cached sizes against
+        let invalid = |violation| Err(BTreeError::Invalid { path: path.clone(), violation });
+        let items = self.items();
+
+        if items.len() > rules.maxkeys {
+            return invalid(Violation::TooManyKeys { keys: items.len(), max: rules.maxkeys });
+        }
+        // the root is the only node allowed to run thin
+        if depth > 0 && items.len() < rules.minkeys {
+            return invalid(Violation::TooFewKeys { keys: items.len(), min: rules.minkeys });
+        }
+        for index in 1..items.len() {
+            if items[index - 1].key >= items[index].key {
+                return invalid(Violation::UnsortedKeys { index });
+            }
+        }
+        let (lower, upper) = bounds;
+        for (index, item) in items.iter().enumerate() {
+            if lower.is_some_and(|lower| item.key <= *lower) || upper.is_some_and(|upper| item.key >= *upper) {
+                return invalid(Violation::KeyOutOfBounds { index });
+            }
+        }
+
+        let internal = match self {
+            Node::Leaf(_) => {
+                let expected = *leaf_depth.get_or_insert(depth);
+                if depth != expected {
+                    return invalid(Violation::UnevenLeaves { depth, expected });
+                }
+                return Ok(items.len());
+            }
+            Node::Internal(internal) => internal,
+        };
+        if internal.children.len() != items.len() + 1 {
+            return invalid(Violation::ChildCount { keys: items.len(), children: internal.children.len() });
+        }
+        let sizes = if rules.counted { internal.children.len() } else { 0 };
+        if internal.sizes.len() != sizes {
+            return invalid(Violation::CounterDrift { counter: "sizes", cached: internal.sizes.len(), actual: sizes });
+        }
+        let mut size = items.len();
+        for (position, child) in internal.children.iter().enumerate() {
+            // child `i` lives between separators `i - 1` and `i`, falling back to our own bounds
+            let lower = position.checked_sub(1).map(|index| &items[index].key).or(lower);
+            let upper = items.get(position).map(|item| &item.key).or(upper);
+            path.push(position);
+            let actual = child.validate(rules, (lower, upper), depth + 1, leaf_depth, path)?;
+            path.pop();
+            if let Some(&cached) = internal.sizes.get(position)
+                && cached != actual
+            {
+                let violation = Violation::CounterDrift { counter: "subtree size", cached, actual };
+                return Err(BTreeError::Invalid { path: path.clone(), violation });
+            }
+            size += actual;
+        }
+        Ok(size)
+    }
+}
+
+impl<K, V> Internal<K, V>
+where
+    K: Ord,
+{
+    fn merge(&mut self, position: usize, sibling: usize, depth: usize) -> usize {
+
+       // whichever way we looked, the pair is always (left, divider, right)
+       let (left, right) = if sibling < position { (sibling, position) } else { (position, sibling) };
+
+       let push_down_key = self.items.remove(left);
+       // take the right node out of the parent rather than copying it
+       let node_2 = self.children.remove(right);
+
+       // merged child reducing children count
+       self.children[left].absorb(push_down_key, node_2);
+       if self.counted() {
+           self.sizes.remove(right);
+           self.recount(left);
+       }
+
+       tracing::debug!(
+           depth,
+           position = left,
+           merged_items = self.children[left].items().len(),
+           parent_items = self.items.len(),
+           "merged siblings",
+       );
+
+       // we're now headed into the merged node
+       left
+    }
+    fn swap(&mut self, position: usize, sibling: usize, depth: usize) -> usize {
+
+       match position.cmp(&sibling) {
+           Ordering::Greater => {
+              // bring over sibling key, and its furthest-right child pointer if it has one
+              let (rightmost, child_swap) = self.children[sibling].pop_last();
+              // update parent key with sibling's -- the divider between us sits at the sibling's index
+              let pushed_parent_key = mem::replace(&mut self.items[sibling], rightmost);
+              // update child key with parent's, the child pointer becomes our furthest-left
+              self.children[position].push_first(pushed_parent_key, child_swap);
+           },
+           Ordering::Less => {
+              // bring over sibling key, and its furthest-left child pointer if it has one
+              let (leftmost, child_swap) = self.children[sibling].pop_first();
+              // update parent key with sibling's
+              let pushed_parent_key = mem::replace(&mut self.items[position], leftmost);
+              // update child key with parent's, the child pointer becomes our furthest-right
+              self.children[position].push_last(pushed_parent_key, child_swap);
+           },
+           _=> {}
+       }
+       self.recount(position);
+       self.recount(sibling);
+
+       tracing::debug!(
+           depth,
+           position,
+           sibling,
+           child_items = self.children[position].items().len(),
+           lender_items = self.children[sibling].items().len(),
+           "rotated key from sibling",
+       );
+       position
+    }
+
+    fn make_enough(&mut self, mut position: usize, rules: &BTreeRules, depth: usize) -> (usize, usize) {
+
+        // returns the position to descend into, which moves left if we merged with a left sibling,
+        // and how far the child's old contents moved right (anything taken from the left goes in front)
+        let mut shift = 0;
+        if !self.children[position].enough(rules) {
+
+            // look left and right: edge children only have the one neighbour
+            let left = position.checked_sub(1);
+            let right = Some(position + 1).filter(|&sibling| sibling < self.children.len());
+
+            // rotating a key over is cheaper than a merge and keeps both nodes fuller,
+            // so ask either neighbour for a spare before folding anyone together
+            let lender = right
+                .into_iter()
+                .chain(left)
+                .find(|&sibling| self.children[sibling].enough(rules));
+
+            if let Some(sibling) = lender {
+                // position may alter
+                position = self.swap(position, sibling, depth);
+                shift = usize::from(sibling < position);
+            }
+            else {
+                // nobody can spare a key. prefer the right neighbour, the last child only has a left
+                let sibling = right.or(left).unwrap();
+                if sibling < position {
+                    shift = self.children[sibling].items().len() + 1;
+                }
+                position = self.merge(position, sibling, depth);
+            }
+        }
+        (position, shift)
+    }
+
+    fn delete<Q>(&mut self, key: &Q, position: usize, found: bool, rules: &BTreeRules, depth: usize) -> Option<Item<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        // A1.iv. we hold the KTD: swap in an order-preserving neighbour from whichever
+        //        side can spare one, or fold both sides together and chase the KTD down into them
+        if found {
+            if self.children[position].enough(rules) {
+                let predecessor = self.children[position].delete_max(rules, depth + 1);
+                self.shrank(position);
+                return Some(mem::replace(&mut self.items[position], predecessor));
+            }
+            if self.children[position + 1].enough(rules) {
+                let successor = self.children[position + 1].delete_min(rules, depth + 1);
+                self.shrank(position + 1);
+                return Some(mem::replace(&mut self.items[position], successor));
+            }
+            let position = self.merge(position, position + 1, depth);
+            let removed = self.children[position].delete(key, rules, depth + 1);
+            self.shrank(position);
+            return removed;
+        }
+
+        // A2: only descend if there is enough in next node in recursion path
+        let (position, _) = self.make_enough(position, rules, depth);
+
+        // Recursion call site
+        let removed = self.children[position].delete(key, rules, depth + 1);
+        if removed.is_some() {
+            self.shrank(position);
+        }
+        removed
+    }
+    fn split_child(&mut self, position: usize, depth: usize) -> usize {
+        let (median, new_node) = self.children[position].split();
+
+        self.children.insert(position + 1, new_node);
+        // `position` is the index direction we're headed down,
+        self.items.insert(position, median);
+        if self.counted() {
+            self.sizes.insert(position + 1, 0);
+            self.recount(position);
+            self.recount(position + 1);
+        }
+
+        tracing::debug!(
+            depth,
+            position,
+            left_items = self.children[position].items().len(),
+            right_items = self.children[position + 1].items().len(),
+            "split full child",
+        );
+        // what's left behind is everything below the median
+        self.children[position].items().len()
+    }
+    fn insert(&mut self, item: Item<K, V>, mut position: usize, rules: &BTreeRules, depth: usize) -> Option<V> {
+        // case 3: on your way down, if you see a full child, split.
+        if self.splittable_child(position, rules) {
+           self.split_child(position, depth);
+
+           // change recursive path in case a split brought up a median into our items making
+           // `position` outdated. the median itself might be the key we're inserting, too.
+           match item.key.cmp(&self.items[position].key) {
+               Ordering::Greater => position += 1,
+               Ordering::Equal => {
+                   return Some(mem::replace(&mut self.items[position].value, item.value));
+               }
+               Ordering::Less => {}
+           }
+        }
+        let old = self.children[position].insert(item, rules, depth + 1);
+        if old.is_none() {
+            self.grew(position);
+        }
+        old
+    }
+    fn splittable_child(&self, position: usize, rules: &BTreeRules) -> bool {
+        !self.children[position].insertable(rules)
+            && self.children.len() < rules.maxchildren
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rules() -> BTreeRules {
+        BTreeRules::new(2)
+    }
+
+    fn node(keys: &[i32], children: Vec<Node<i32, ()>>) -> Node<i32, ()> {
+        let items = keys.iter().map(|&key| Item { key, value: () }).collect();
+        if children.is_empty() {
+            Node::Leaf(Leaf { items })
+        } else {
+            // hand-built nodes keep subtree sizes, so every rotate and merge below also checks them
+            let sizes = children.iter().map(Node::size).collect();
+            Node::Internal(Internal { items, children, sizes })
+        }
+    }
+
+    fn parent(keys: &[i32], children: Vec<Node<i32, ()>>) -> Internal<i32, ()> {
+        match node(keys, children) {
+            Node::Internal(internal) => internal,
+            Node::Leaf(_) => unreachable!(),
+        }
+    }
+
+    fn keys(node: &Node<i32, ()>) -> Vec<i32> {
+        node.items().iter().map(|item| item.key).collect()
+    }
+
+    fn child_keys(node: &Node<i32, ()>) -> Vec<Vec<i32>> {
+        node.children().iter().map(keys).collect()
+    }
+
+    fn parent_keys(parent: &Internal<i32, ()>) -> Vec<i32> {
+        parent.items.iter().map(|item| item.key).collect()
+    }
+
+    fn assert_shape(parent: &Internal<i32, ()>) {
+        assert_eq!(parent.children.len(), parent.items.len() + 1);
+        let sizes: Vec<usize> = parent.children.iter().map(Node::size).collect();
+        assert_eq!(parent.sizes, sizes);
+        for child in &parent.children {
+            if let Node::Internal(internal) = child {
+                assert_shape(internal);
+            }
+        }
+    }
+
+    #[test]
+    fn swap_from_right_leaf() {
+        //      [10]              [20]
+        //     /    \     ->     /    \
+        //   [5]  [20,30]     [5,10]  [30]
+        let mut parent = parent(&[10], vec![node(&[5], vec![]), node(&[20, 30], vec![])]);
+        assert_eq!(parent.swap(0, 1, 0), 0);
+        assert_eq!(parent_keys(&parent), vec![20]);
+        assert_eq!(keys(&parent.children[0]), vec![5, 10]);
+        assert_eq!(keys(&parent.children[1]), vec![30]);
+        assert_shape(&parent);
+    }
+
+    #[test]
+    fn swap_from_left_leaf() {
+        //       [30]              [20]
+        //      /    \     ->     /    \
+        //  [10,20]  [40]       [10]  [30,40]
+        let mut parent = parent(&[30], vec![node(&[10, 20], vec![]), node(&[40], vec![])]);
+        assert_eq!(parent.swap(1, 0, 0), 1);
+        assert_eq!(parent_keys(&parent), vec![20]);
+        assert_eq!(keys(&parent.children[0]), vec![10]);
+        assert_eq!(keys(&parent.children[1]), vec![30, 40]);
+        assert_shape(&parent);
+    }
+
+    #[test]
+    fn swap_from_right_internal() {
+        // the right sibling's leftmost child follows its key over
+        let mut parent = parent(&[10], vec![
+            node(&[5], vec![node(&[1], vec![]), node(&[7], vec![])]),
+            node(&[20, 30], vec![node(&[15], vec![]), node(&[25], vec![]), node(&[35], vec![])]),
+        ]);
+        parent.swap(0, 1, 0);
+        assert_eq!(parent_keys(&parent), vec![20]);
+        assert_eq!(keys(&parent.children[0]), vec![5, 10]);
+        assert_eq!(child_keys(&parent.children[0]), vec![vec![1], vec![7], vec![15]]);
+        assert_eq!(keys(&parent.children[1]), vec![30]);
+        assert_eq!(child_keys(&parent.children[1]), vec![vec![25], vec![35]]);
+        assert_shape(&parent);
+    }
+
+    #[test]
+    fn swap_from_left_internal() {
+        // the left sibling's rightmost child follows its key over
+        let mut parent = parent(&[30], vec![
+            node(&[10, 20], vec![node(&[5], vec![]), node(&[15], vec![]), node(&[25], vec![])]),
+            node(&[40], vec![node(&[35], vec![]), node(&[45], vec![])]),
+        ]);
+        parent.swap(1, 0, 0);
+        assert_eq!(parent_keys(&parent), vec![20]);
+        assert_eq!(keys(&parent.children[0]), vec![10]);
+        assert_eq!(child_keys(&parent.children[0]), vec![vec![5], vec![15]]);
+        assert_eq!(keys(&parent.children[1]), vec![30, 40]);
+        assert_eq!(child_keys(&parent.children[1]), vec![vec![25], vec![35], vec![45]]);
+        assert_shape(&parent);
+    }
+
+    #[test]
+    fn merge_with_right() {
+        let mut parent = parent(&[10, 20], vec![node(&[5], vec![]), node(&[15], vec![]), node(&[25], vec![])]);
+        assert_eq!(parent.merge(0, 1, 0), 0);
+        assert_eq!(parent_keys(&parent), vec![20]);
+        assert_eq!(keys(&parent.children[0]), vec![5, 10, 15]);
+        assert_eq!(keys(&parent.children[1]), vec![25]);
+        assert_shape(&parent);
+    }
+
+    #[test]
+    fn merge_with_left() {
+        let mut parent = parent(&[10, 20], vec![node(&[5], vec![]), node(&[15], vec![]), node(&[25], vec![])]);
+        assert_eq!(parent.merge(2, 1, 0), 1);
+        assert_eq!(parent_keys(&parent), vec![10]);
+        assert_eq!(keys(&parent.children[0]), vec![5]);
+        assert_eq!(keys(&parent.children[1]), vec![15, 20, 25]);
+        assert_shape(&parent);
+    }
+
+    #[test]
+    fn merge_internal_children() {
+        let mut parent = parent(&[10], vec![
+            node(&[5], vec![node(&[1], vec![]), node(&[7], vec![])]),
+            node(&[15], vec![node(&[12], vec![]), node(&[17], vec![])]),
+        ]);
+        parent.merge(1, 0, 0);
+        assert_eq!(parent_keys(&parent), Vec::<i32>::new());
+        assert_eq!(keys(&parent.children[0]), vec![5, 10, 15]);
+        assert_eq!(child_keys(&parent.children[0]), vec![vec![1], vec![7], vec![12], vec![17]]);
+        assert_shape(&parent);
+    }
+
+    #[test]
+    fn degree_for_node_size() {
+        // 3 + 170 * 8 + 169 * 16 = 4067 fits in 4096, one degree more (4115) doesn't
+        assert_eq!(BTreeRules::for_node_size::<u64, u64>(4096).degree(), 85);
+        assert_eq!(BTreeRules::for_node_size::<(), ()>(4096).degree(), 255);
+        assert_eq!(BTreeRules::fitting(64, 0, 8, 8).degree, 2);
+        // a node of a kilobyte in memory, sized the same way
+        let rules = BTreeRules::for_node_size::<u32, u32>(1024);
+        assert_eq!((rules.degree(), rules.max_keys(), rules.min_keys()), (32, 63, 31));
+    }
+
+    #[test]
+    #[should_panic(expected = "too small for any B-Tree")]
+    fn node_size_too_small_panics() {
+        BTreeRules::for_node_size::<u64, u64>(64);
+    }
+
+    #[test]
+    #[should_panic(expected = "fixed width")]
+    fn node_size_needs_fixed_widths() {
+        BTreeRules::for_node_size::<String, u64>(4096);
+    }
+
+    #[test]
+    fn split_per_variant() {
+        let mut leaf = node(&[1, 2, 3], vec![]);
+        let (median, right) = leaf.split();
+        assert_eq!(median.key, 2);
+        assert_eq!((keys(&leaf), keys(&right)), (vec![1], vec![3]));
+        assert!(matches!((&leaf, &right), (Node::Leaf(_), Node::Leaf(_))));
+
+        let mut internal = node(&[10, 20, 30], vec![
+            node(&[5], vec![]), node(&[15], vec![]), node(&[25], vec![]), node(&[35], vec![]),
+        ]);
+        let (median, right) = internal.split();
+        assert_eq!(median.key, 20);
+        assert_eq!((keys(&internal), keys(&right)), (vec![10], vec![30]));
+        assert_eq!(child_keys(&internal), vec![vec![5], vec![15]]);
+        assert_eq!(child_keys(&right), vec![vec![25], vec![35]]);
+    }
+
+    #[test]
+    fn make_enough_borrows_from_left_before_merging() {
+        // middle child is thin, right neighbour is thin too, but the left one can spare a key
+        let mut parent = parent(&[20, 40], vec![node(&[5, 10], vec![]), node(&[30], vec![]), node(&[50], vec![])]);
+        assert_eq!(parent.make_enough(1, &rules(), 0), (1, 1));
+        assert_eq!(parent_keys(&parent), vec![10, 40]);
+        assert_eq!(keys(&parent.children[0]), vec![5]);
+        assert_eq!(keys(&parent.children[1]), vec![20, 30]);
+        assert_eq!(keys(&parent.children[2]), vec![50]);
+        assert_shape(&parent);
+    }
+
+    #[test]
+    fn make_enough_prefers_right_rotation() {
+        let mut parent = parent(&[20, 40], vec![node(&[5, 10], vec![]), node(&[30], vec![]), node(&[45, 50], vec![])]);
+        assert_eq!(parent.make_enough(1, &rules(), 0), (1, 0));
+        assert_eq!(parent_keys(&parent), vec![20, 45]);
+        assert_eq!(keys(&parent.children[1]), vec![30, 40]);
+        assert_shape(&parent);
+    }
+
+    #[test]
+    fn make_enough_merges_when_nobody_can_lend() {
+        let mut parent = parent(&[20, 40], vec![node(&[10], vec![]), node(&[30], vec![]), node(&[50], vec![])]);
+        // middle child folds into its right neighbour
+        assert_eq!(parent.make_enough(1, &rules(), 0), (1, 0));
+        assert_eq!(parent_keys(&parent), vec![20]);
+        assert_eq!(keys(&parent.children[1]), vec![30, 40, 50]);
+
+        // last child can only fold into its left neighbour
+        let mut parent = self::parent(&[20, 40], vec![node(&[10], vec![]), node(&[30], vec![]), node(&[50], vec![])]);
+        // everything in it now sits behind the left child's key and the separator
+        assert_eq!(parent.make_enough(2, &rules(), 0), (1, 2));
+        assert_eq!(parent_keys(&parent), vec![20]);
+        assert_eq!(keys(&parent.children[1]), vec![30, 40, 50]);
+        assert_shape(&parent);
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::codec::Codec;
+use crate::error::DiskError;
+use crate::pool::{BufferPool, Eviction};
+use crate::shadow::Shadows;
+use crate::wal::{self, Durability, Wal, checksum};
+
+// Files are a run of fixed-size pages. Page 0 is the header; every other page holds one node, or
+// sits on the free list waiting to be handed out again (its first 8 bytes naming the next free
+// page, 0 ending the list). Copy-on-write files have two headers, pages 0 and 1, and keep their
+// free pages in memory instead.
+
+pub(crate) const PAGE_SIZE: usize = 4096;
+
+pub(crate) const HEADER_SIZE: usize = 64;
+
+// how big the log gets before a commit checkpoints it, 4 MiB of pages or so
+const CHECKPOINT_BYTES: u64 = 1024 * PAGE_SIZE as u64;
+
+// 256 KiB of pages, enough for the top few levels of most trees
+pub(crate) const DEFAULT_FRAMES: usize = 64;
+
+pub(crate) type PageId = u64;
+
+const MAGIC: &[u8; 8] = b"btree\0p1";
+const COPY_ON_WRITE_MAGIC: &[u8; 8] = b"btree\0c1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub(crate) copy_on_write: bool,
+    // 0 for a tree whose nodes are sized by bytes rather than by a degree
+    pub(crate) degree: usize,
+    pub(crate) root: PageId,
+    pub(crate) len: usize,
+    // pages in the file, header included
+    pub(crate) pages: u64,
+    pub(crate) free: PageId,
+    // commits so far; copy-on-write files open with whichever header has more
+    pub(crate) txn: u64,
+}
+
+impl Header {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut page = if self.copy_on_write { COPY_ON_WRITE_MAGIC } else { MAGIC }.to_vec();
+        (PAGE_SIZE as u32).encode(&mut page);
+        (self.degree as u32).encode(&mut page);
+        self.root.encode(&mut page);
+        (self.len as u64).encode(&mut page);
+        self.pages.encode(&mut page);
+        self.free.encode(&mut page);
+        self.txn.encode(&mut page);
+        checksum(&page).encode(&mut page);
+        debug_assert_eq!(page.len(), HEADER_SIZE);
+        page
+    }
+    pub(crate) fn decode(id: PageId, mut page: &[u8]) -> Result<Self, DiskError> {
+        let corrupt = |reason| DiskError::Corrupt { page: id, reason };
+        let copy_on_write = match page.get(..MAGIC.len()) {
+            Some(magic) if magic == MAGIC => false,
+            Some(magic) if magic == COPY_ON_WRITE_MAGIC => true,
+            _ => return Err(corrupt("not a B-Tree file")),
+        };
+        let summed = page.get(..HEADER_SIZE - 8).ok_or(corrupt("header is cut short"))?;
+        if u64::decode(&mut &page[HEADER_SIZE - 8..]) != Some(checksum(summed)) {
+            return Err(corrupt("header checksum doesn't match"));
+        }
+        page = &page[MAGIC.len()..];
+        if u32::decode(&mut page) != Some(PAGE_SIZE as u32) {
+            return Err(corrupt("written with a different page size"));
+        }
+        let degree = u32::decode(&mut page).unwrap();
+        let mut field = || u64::decode(&mut page).unwrap();
+        let header = Header {
+            copy_on_write,
+            degree: degree as usize,
+            root: field(),
+            len: field() as usize,
+            pages: field(),
+            free: field(),
+            txn: field(),
+        };
+        let first = first_node(copy_on_write);
+        if header.degree == 1 || header.root < first || header.root >= header.pages || header.free >= header.pages {
+            return Err(corrupt("header fields are out of range"));
+        }
+        Ok(header)
+    }
+}
+
+// the pages before it are headers
+pub(crate) fn first_node(copy_on_write: bool) -> PageId {
+    if copy_on_write { 2 } else { 1 }
+}
+
+// How the file is kept whole through a crash: by a write-ahead log, or by never overwriting a page
+// the last commit reaches.
+#[derive(Debug)]
+pub(crate) enum Journal {
+    Log(Wal),
+    Shadow(Shadows),
+}
+
+// Reads and writes whole pages through a buffer pool, and hands out page ids. With a log, pages
+// leave the pool for the log, evicted or committed, and only reach their place in the file when
+// the log is checkpointed, so the file only ever holds whole changes. Copy-on-write, they go
+// straight to the file, where no committed header reaches them yet.
+#[derive(Debug)]
+pub(crate) struct Pager {
+    file: File,
+    path: PathBuf,
+    pub(crate) header: Header,
+    // the header as of the last commit, which is all a checkpoint may write: `header` runs ahead
+    // of it while a change is under way
+    committed: Header,
+    pub(crate) pool: BufferPool,
+    pub(crate) journal: Journal,
+    pub(crate) durability: Durability,
+    // commits since the last one that waited for the disk
+    unsynced: usize,
+}
+
+impl Pager {
+    pub(crate) fn create(path: &Path, degree: usize, copy_on_write: bool) -> Result<Self, DiskError> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        let journal = match copy_on_write {
+            true => {
+                // a log left by a tree that used to be here would be taken for this one's on open
+                match fs::remove_file(wal::wal_path(path)) {
+                    Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+                    _ => {}
+                }
+                Journal::Shadow(Shadows::default())
+            }
+            false => Journal::Log(Wal::create(&wal::wal_path(path))?),
+        };
+        // the caller allocates and writes the root before anything reads the header back
+        let first = first_node(copy_on_write);
+        let header = Header { copy_on_write, degree, root: first, len: 0, pages: first, free: 0, txn: 0 };
+        Ok(Pager::new(file, path, header, journal))
+    }
+
+    pub(crate) fn open(path: &Path) -> Result<Self, DiskError> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let first = read_header(&mut file, 0);
+        let second = read_header(&mut file, 1);
+        // A file kept by a log goes with the log's last commit, if it has one, which is newer than
+        // anything the file says. So does one whose header is torn or was never written, if there's
+        // a log beside it: a checkpoint may have crashed part way, or the file never got one. One
+        // whose second header is a copy-on-write one never had a log, whatever is lying beside it.
+        let wal_path = wal::wal_path(path);
+        let logged = match (&first, &second) {
+            (Ok(header), _) => !header.copy_on_write,
+            (Err(_), Ok(header)) if header.copy_on_write => false,
+            (Err(_), _) => wal_path.exists(),
+        };
+        if logged {
+            let (wal, recovered) = Wal::open(&wal_path)?;
+            if let Some(header) = recovered.or(first.as_ref().ok().copied()) {
+                let mut pager = Pager::new(file, path, header, Journal::Log(wal));
+                if recovered.is_some() {
+                    pager.checkpoint(true)?;
+                }
+                return pager.check_length();
+            }
+        }
+
+        // a copy-on-write file goes with the newer of its headers, or the only whole one
+        let header = match (first, second) {
+            (Ok(first), Ok(second)) if second.copy_on_write && second.txn > first.txn => second,
+            (Ok(first), _) => first,
+            (Err(_), Ok(second)) if second.copy_on_write => second,
+            (Err(error), _) => return Err(error),
+        };
+        Pager::new(file, path, header, Journal::Shadow(Shadows::opened(header.txn))).check_length()
+    }
+
+    fn new(file: File, path: &Path, header: Header, journal: Journal) -> Self {
+        let pool = BufferPool::new(DEFAULT_FRAMES, Eviction::default());
+        let path = path.to_path_buf();
+        Pager { file, path, header, committed: header, pool, journal, durability: Durability::default(), unsynced: 0 }
+    }
+    fn check_length(self) -> Result<Self, DiskError> {
+        if self.file.metadata()?.len() < self.header.pages * PAGE_SIZE as u64 {
+            return Err(DiskError::Corrupt { page: 0, reason: "file is shorter than the header says" });
+        }
+        Ok(self)
+    }
+
+    // copy-on-write, on open: every page past the headers that the tree doesn't reach is free
+    pub(crate) fn reclaim(&mut self, reached: &HashSet<PageId>) {
+        if let Journal::Shadow(shadows) = &mut self.journal {
+            shadows.reclaim((first_node(true)..self.header.pages).filter(|id| !reached.contains(id)));
+        }
+    }
+
+    // a handle on the file of its own, for a snapshot to read committed pages through: a clone of
+    // ours would share its position, and a snapshot may be reading on another thread
+    pub(crate) fn reader(&self) -> Result<File, DiskError> {
+        Ok(File::open(&self.path)?)
+    }
+
+    // swaps in a pool of a different size or policy, writing out whatever the old one had changed
+    pub(crate) fn replace_pool(&mut self, pool: BufferPool) -> Result<(), DiskError> {
+        self.write_back()?;
+        self.pool = pool;
+        Ok(())
+    }
+
+    // hands `read` the page, pinned in the pool for as long as it looks
+    pub(crate) fn read<T>(&mut self, id: PageId, read: impl FnOnce(&[u8]) -> T) -> Result<T, DiskError> {
+        if id < first_node(self.header.copy_on_write) || id >= self.header.pages {
+            return Err(DiskError::Corrupt { page: id, reason: "page id is out of range" });
+        }
+        let frame = match self.pool.pin(id) {
+            Some(frame) => frame,
+            None => {
+                // the log has the page if it's changed since the last checkpoint
+                let mut page = vec![0; PAGE_SIZE];
+                match &mut self.journal {
+                    Journal::Log(wal) if let Some(offset) = wal.find(id) => wal.read_page(offset, &mut page)?,
+                    _ => {
+                        self.file.seek(SeekFrom::Start(id * PAGE_SIZE as u64))?;
+                        self.file.read_exact(&mut page)?;
+                    }
+                }
+                self.admit(id, page, false)?
+            }
+        };
+        let value = read(self.pool.page(frame));
+        self.pool.unpin(frame);
+        Ok(value)
+    }
+
+    pub(crate) fn write(&mut self, id: PageId, bytes: &[u8]) -> Result<(), DiskError> {
+        // a short page is padded out, so the file always ends on a page boundary
+        debug_assert!(bytes.len() <= PAGE_SIZE, "page {id} overflowed to {} bytes", bytes.len());
+        debug_assert!(
+            !matches!(&self.journal, Journal::Shadow(shadows) if !shadows.is_fresh(id)),
+            "page {id} is still reachable from the last commit"
+        );
+        let mut page = bytes.to_vec();
+        page.resize(PAGE_SIZE, 0);
+        // a page about to be overwritten whole needn't be read in first
+        match self.pool.resident(id) {
+            Some(frame) => self.pool.overwrite(frame, page),
+            None => {
+                let frame = self.admit(id, page, true)?;
+                self.pool.unpin(frame);
+            }
+        }
+        Ok(())
+    }
+
+    fn admit(&mut self, id: PageId, page: Vec<u8>, dirty: bool) -> Result<usize, DiskError> {
+        let frames = self.pool.capacity();
+        let (frame, evicted) = self.pool.admit(id, page, dirty).ok_or(DiskError::PoolExhausted { frames })?;
+        if let Some((evicted, page)) = evicted {
+            match &mut self.journal {
+                Journal::Log(wal) => wal.append_page(evicted, &page)?,
+                Journal::Shadow(_) => write_page(&mut self.file, evicted, &page)?,
+            }
+        }
+        Ok(frame)
+    }
+
+    pub(crate) fn allocate(&mut self) -> Result<PageId, DiskError> {
+        if let Journal::Shadow(shadows) = &mut self.journal {
+            let pages = &mut self.header.pages;
+            return Ok(shadows.allocate(|| {
+                *pages += 1;
+                *pages - 1
+            }));
+        }
+        // reuse a freed page before growing the file
+        if self.header.free != 0 {
+            let id = self.header.free;
+            self.header.free = self.read(id, |mut page| u64::decode(&mut page).unwrap())?;
+            return Ok(id);
+        }
+        let id = self.header.pages;
+        self.header.pages += 1;
+        Ok(id)
+    }
+
+    pub(crate) fn free(&mut self, id: PageId) -> Result<(), DiskError> {
+        if let Journal::Shadow(shadows) = &mut self.journal {
+            shadows.retire(id);
+            return Ok(());
+        }
+        let mut page = Vec::new();
+        self.header.free.encode(&mut page);
+        self.write(id, &page)?;
+        self.header.free = id;
+        Ok(())
+    }
+
+    // Copy-on-write, a fresh page to copy `id` to before changing it, unless it's fresh already.
+    // `id` is retired in its place.
+    pub(crate) fn shadow(&mut self, id: PageId) -> Result<Option<PageId>, DiskError> {
+        match &self.journal {
+            Journal::Shadow(shadows) if !shadows.is_fresh(id) => {}
+            _ => return Ok(None),
+        }
+        let copy = self.allocate()?;
+        self.free(id)?;
+        Ok(Some(copy))
+    }
+
+    fn write_back(&mut self) -> Result<(), DiskError> {
+        for (id, page) in self.pool.take_dirty() {
+            match &mut self.journal {
+                Journal::Log(wal) => wal.append_page(id, page)?,
+                Journal::Shadow(_) => write_page(&mut self.file, id, page)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn commit(&mut self) -> Result<(), DiskError> {
+        self.unsynced += 1;
+        let sync = match self.durability {
+            Durability::EveryCommit => true,
+            Durability::Group(commits) => self.unsynced >= commits,
+            Durability::Never => false,
+        };
+        if sync {
+            self.unsynced = 0;
+        }
+        // the pages go out ahead of the header that points at them
+        self.write_back()?;
+        self.header.txn += 1;
+        match &mut self.journal {
+            Journal::Log(wal) => {
+                wal.commit(&self.header)?;
+                self.committed = self.header;
+                if sync {
+                    wal.sync()?;
+                }
+                if wal.bytes() >= CHECKPOINT_BYTES {
+                    self.checkpoint(self.durability != Durability::Never)?;
+                }
+            }
+            Journal::Shadow(shadows) => {
+                // Unless nothing waits for the disk, the pages are on it before their header goes
+                // out, so whichever header a crash leaves on disk reaches only whole pages. Grouped,
+                // the header itself needn't wait: losing it only loses the change.
+                if self.durability != Durability::Never {
+                    self.file.sync_data()?;
+                }
+                write_header(&mut self.file, &self.header)?;
+                self.committed = self.header;
+                // the change is in once its header is, even if waiting for the disk then fails
+                let synced = if sync { self.file.sync_data() } else { Ok(()) };
+                if synced.is_ok() && (sync || self.durability == Durability::Never) {
+                    shadows.durable = self.header.txn;
+                }
+                shadows.committed(self.header.txn);
+                synced?;
+            }
+        }
+        Ok(())
+    }
+
+    // Undoes a change that failed part way, leaving the tree as its last commit did: nothing the
+    // change wrote is reachable from there, so it's enough to forget it.
+    pub(crate) fn roll_back(&mut self) -> Result<(), DiskError> {
+        self.header = self.committed;
+        self.pool.clear();
+        tracing::debug!(txn = self.header.txn, "rolled back to the last commit");
+        match &mut self.journal {
+            Journal::Log(wal) => wal.roll_back(),
+            Journal::Shadow(shadows) => {
+                shadows.roll_back(self.header.pages);
+                Ok(())
+            }
+        }
+    }
+
+    // Copies the newest committed image of every page in the log into the file, then the header,
+    // and empties the log. With `sync`, the log is on disk before the file starts changing and the
+    // file is on disk before the log is emptied, so a crash part way leaves the log to redo it.
+    pub(crate) fn checkpoint(&mut self, sync: bool) -> Result<(), DiskError> {
+        let Journal::Log(wal) = &mut self.journal else { return Ok(()) };
+        if sync {
+            wal.sync()?;
+        }
+        let mut page = vec![0; PAGE_SIZE];
+        let pages = wal.committed();
+        for &(id, offset) in &pages {
+            wal.read_page(offset, &mut page)?;
+            write_page(&mut self.file, id, &page)?;
+        }
+        write_header(&mut self.file, &self.committed)?;
+        if sync {
+            self.file.sync_all()?;
+        }
+        wal.truncate()?;
+        tracing::debug!(pages = pages.len(), "checkpointed log");
+        Ok(())
+    }
+
+    // everything committed, in its place in the file and on the disk
+    pub(crate) fn flush(&mut self) -> Result<(), DiskError> {
+        match &mut self.journal {
+            Journal::Log(_) => self.checkpoint(true)?,
+            Journal::Shadow(shadows) => {
+                self.file.sync_all()?;
+                shadows.durable = self.header.txn;
+            }
+        }
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        // Only what's committed goes into the file: pages an operation that failed part way left
+        // behind stay out. Nothing to hand an error to here; `flush` first to see one.
+        let _ = match self.durability {
+            Durability::Never => self.checkpoint(false),
+            _ => self.flush(),
+        };
+    }
+}
+
+fn read_header(file: &mut File, id: PageId) -> Result<Header, DiskError> {
+    let mut page = vec![0; PAGE_SIZE];
+    file.seek(SeekFrom::Start(id * PAGE_SIZE as u64))?;
+    file.read_exact(&mut page).map_err(|_| DiskError::Corrupt { page: id, reason: "file is cut short" })?;
+    Header::decode(id, &page)
+}
+
+fn write_page(file: &mut File, id: PageId, page: &[u8]) -> Result<(), DiskError> {
+    file.seek(SeekFrom::Start(id * PAGE_SIZE as u64))?;
+    file.write_all(page)?;
+    Ok(())
+}
+
+// into page 0, or copy-on-write, into whichever header page doesn't hold the one before it
+fn write_header(file: &mut File, header: &Header) -> Result<(), DiskError> {
+    let mut page = header.encode();
+    page.resize(PAGE_SIZE, 0);
+    let slot = if header.copy_on_write { header.txn % 2 } else { 0 };
+    write_page(file, slot, &page)
+}
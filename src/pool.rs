@@ -0,0 +1,340 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::pager::PageId;
+
+// Keeps recently used pages in memory, so a descent through the top of the tree doesn't go to the
+// file for every node. The pool does no i/o of its own: the pager reads a page in on a miss and
+// logs whatever dirty page the pool hands it on an eviction.
+
+/// How a [`DiskBTree`](crate::DiskBTree)'s buffer pool picks the page to
+/// evict once all its frames are in use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Eviction {
+    /// The least recently used page goes first.
+    #[default]
+    Lru,
+    /// A hand sweeps the frames, sparing pages used since it last passed
+    /// them. Close to LRU, for less bookkeeping per hit.
+    Clock,
+    /// Pages start out on a short probationary queue and only join the main
+    /// LRU queue if they're asked for again after falling off it, so a
+    /// single scan through the tree can't flush out the pages that are hot.
+    TwoQueue,
+}
+
+/// Hit and miss counts for a [`DiskBTree`](crate::DiskBTree)'s buffer pool,
+/// for sizing it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Page reads served from memory.
+    pub hits: u64,
+    /// Page reads that had to go to the file or its log.
+    pub misses: u64,
+    /// Pages dropped from memory to make room for others.
+    pub evictions: u64,
+    /// Evicted pages that were written out to the log first, because they
+    /// had changed since they were read.
+    pub write_backs: u64,
+}
+
+impl PoolStats {
+    /// The fraction of page reads served from memory, or 0 before any reads.
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            reads => self.hits as f64 / reads as f64,
+        }
+    }
+}
+
+// a changed page pushed out of the pool, still to be written back
+pub(crate) type Evicted = (PageId, Vec<u8>);
+
+#[derive(Debug)]
+struct Frame {
+    id: PageId,
+    page: Vec<u8>,
+    pins: usize,
+    dirty: bool,
+    // when it was last used, or for a page on 2Q's probationary queue, when it came in
+    used: u64,
+    // CLOCK: used since the hand last passed
+    referenced: bool,
+    // 2Q: on the main queue rather than the probationary one
+    hot: bool,
+}
+
+#[derive(Debug)]
+pub(crate) struct BufferPool {
+    frames: Vec<Frame>,
+    // which frame holds each resident page
+    table: HashMap<PageId, usize>,
+    capacity: usize,
+    eviction: Eviction,
+    tick: u64,
+    hand: usize,
+    // 2Q: pages recently evicted off the probationary queue, to be let straight onto the main one
+    // if they're asked for again soon
+    ghosts: VecDeque<PageId>,
+    pub(crate) stats: PoolStats,
+}
+
+impl BufferPool {
+    pub(crate) fn new(capacity: usize, eviction: Eviction) -> Self {
+        assert!(capacity > 0, "a buffer pool needs at least one frame");
+        BufferPool {
+            frames: Vec::with_capacity(capacity),
+            table: HashMap::new(),
+            capacity,
+            eviction,
+            tick: 0,
+            hand: 0,
+            ghosts: VecDeque::new(),
+            stats: PoolStats::default(),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // pins the frame holding `id` if the page is resident, counting a hit or a miss
+    pub(crate) fn pin(&mut self, id: PageId) -> Option<usize> {
+        let Some(&frame) = self.table.get(&id) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        self.touch(frame);
+        self.frames[frame].pins += 1;
+        Some(frame)
+    }
+    pub(crate) fn unpin(&mut self, frame: usize) {
+        let frame = &mut self.frames[frame];
+        debug_assert!(frame.pins > 0, "page {} unpinned more often than pinned", frame.id);
+        frame.pins -= 1;
+    }
+
+    // Brings `page` in as `id`, pinned, making room if need be. Hands back its frame and the page
+    // it evicted if that one has to be written back first, or `None` if every frame is pinned.
+    pub(crate) fn admit(&mut self, id: PageId, page: Vec<u8>, dirty: bool) -> Option<(usize, Option<Evicted>)> {
+        debug_assert!(!self.table.contains_key(&id), "page {id} is already resident");
+        self.tick += 1;
+        let hot = match self.eviction {
+            Eviction::TwoQueue => self.forget_ghost(id),
+            _ => false,
+        };
+        let admitted = Frame { id, page, pins: 1, dirty, used: self.tick, referenced: true, hot };
+        if self.frames.len() < self.capacity {
+            self.frames.push(admitted);
+            self.table.insert(id, self.frames.len() - 1);
+            return Some((self.frames.len() - 1, None));
+        }
+
+        let frame = self.victim()?;
+        let evicted = std::mem::replace(&mut self.frames[frame], admitted);
+        self.table.remove(&evicted.id);
+        self.table.insert(id, frame);
+        self.stats.evictions += 1;
+        if self.eviction == Eviction::TwoQueue && !evicted.hot {
+            self.remember_ghost(evicted.id);
+        }
+        tracing::trace!(page = evicted.id, dirty = evicted.dirty, for_page = id, "evicted page");
+        if !evicted.dirty {
+            return Some((frame, None));
+        }
+        self.stats.write_backs += 1;
+        Some((frame, Some((evicted.id, evicted.page))))
+    }
+
+    pub(crate) fn page(&self, frame: usize) -> &[u8] {
+        &self.frames[frame].page
+    }
+    // replaces a resident page's bytes, which now need writing back
+    pub(crate) fn overwrite(&mut self, frame: usize, page: Vec<u8>) {
+        self.touch(frame);
+        let frame = &mut self.frames[frame];
+        frame.page = page;
+        frame.dirty = true;
+    }
+    pub(crate) fn resident(&self, id: PageId) -> Option<usize> {
+        self.table.get(&id).copied()
+    }
+
+    // the pages changed since they were read, marked clean on the assumption the caller writes
+    // them all back
+    pub(crate) fn take_dirty(&mut self) -> Vec<(PageId, &[u8])> {
+        self.frames
+            .iter_mut()
+            .filter(|frame| frame.dirty)
+            .map(|frame| {
+                frame.dirty = false;
+                (frame.id, &frame.page[..])
+            })
+            .collect()
+    }
+
+    // Forgets every page. After a failed change, dirty ones hold changes no commit will claim,
+    // and clean ones may have been read back in from where an eviction wrote them.
+    pub(crate) fn clear(&mut self) {
+        debug_assert!(self.frames.iter().all(|frame| frame.pins == 0), "cleared a pool with pages pinned");
+        self.frames.clear();
+        self.table.clear();
+        self.ghosts.clear();
+        self.hand = 0;
+    }
+
+    fn touch(&mut self, frame: usize) {
+        self.tick += 1;
+        let frame = &mut self.frames[frame];
+        frame.referenced = true;
+        // a page on 2Q's probationary queue keeps its place, however often it's hit there
+        if self.eviction != Eviction::TwoQueue || frame.hot {
+            frame.used = self.tick;
+        }
+    }
+
+    fn victim(&mut self) -> Option<usize> {
+        match self.eviction {
+            Eviction::Lru => self.oldest(|_| true),
+            Eviction::Clock => self.sweep(),
+            Eviction::TwoQueue => {
+                // the probationary queue gets a quarter of the frames, the main queue the rest
+                let cold = self.frames.iter().filter(|frame| !frame.hot).count();
+                let (first, second) = if cold > (self.capacity / 4).max(1) { (false, true) } else { (true, false) };
+                self.oldest(|frame| frame.hot == first).or_else(|| self.oldest(|frame| frame.hot == second))
+            }
+        }
+    }
+    fn oldest(&self, eligible: impl Fn(&Frame) -> bool) -> Option<usize> {
+        (0..self.frames.len())
+            .filter(|&frame| self.frames[frame].pins == 0 && eligible(&self.frames[frame]))
+            .min_by_key(|&frame| self.frames[frame].used)
+    }
+    fn sweep(&mut self) -> Option<usize> {
+        // two full turns clear every reference bit, so if nothing's come up by then it's all pinned
+        for _ in 0..2 * self.frames.len() {
+            let frame = self.hand;
+            self.hand = (self.hand + 1) % self.frames.len();
+            let candidate = &mut self.frames[frame];
+            if candidate.pins > 0 {
+                continue;
+            }
+            if !candidate.referenced {
+                return Some(frame);
+            }
+            candidate.referenced = false;
+        }
+        None
+    }
+
+    fn remember_ghost(&mut self, id: PageId) {
+        // remembered for about half as many pages as the pool holds
+        if self.ghosts.len() >= (self.capacity / 2).max(1) {
+            self.ghosts.pop_front();
+        }
+        self.ghosts.push_back(id);
+    }
+    fn forget_ghost(&mut self, id: PageId) -> bool {
+        let Some(position) = self.ghosts.iter().position(|&ghost| ghost == id) else { return false };
+        self.ghosts.remove(position);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // reads `id` through the pool the way the pager does, bringing it in on a miss
+    fn read(pool: &mut BufferPool, id: PageId) {
+        let frame = match pool.pin(id) {
+            Some(frame) => frame,
+            None => {
+                let (frame, evicted) = pool.admit(id, vec![id as u8], false).unwrap();
+                assert!(evicted.is_none(), "nothing was dirty");
+                frame
+            }
+        };
+        pool.unpin(frame);
+    }
+
+    fn resident(pool: &BufferPool) -> Vec<PageId> {
+        let mut pages: Vec<_> = pool.table.keys().copied().collect();
+        pages.sort();
+        pages
+    }
+
+    #[test]
+    fn lru_evicts_the_least_recently_used() {
+        let mut pool = BufferPool::new(3, Eviction::Lru);
+        for id in [1, 2, 3, 1, 4] {
+            read(&mut pool, id);
+        }
+        assert_eq!(resident(&pool), [1, 3, 4]);
+        assert_eq!(pool.stats, PoolStats { hits: 1, misses: 4, evictions: 1, write_backs: 0 });
+    }
+
+    #[test]
+    fn clock_gives_used_pages_a_second_chance() {
+        let mut pool = BufferPool::new(3, Eviction::Clock);
+        for id in [1, 2, 3, 4] {
+            read(&mut pool, id);
+        }
+        // all three were referenced, so the hand went all the way round and took the first
+        assert_eq!(resident(&pool), [2, 3, 4]);
+        read(&mut pool, 2);
+        read(&mut pool, 5);
+        // 2 was used since the hand cleared it, 3 wasn't
+        assert_eq!(resident(&pool), [2, 4, 5]);
+    }
+
+    #[test]
+    fn two_queue_shrugs_off_a_scan() {
+        for (eviction, survives) in [(Eviction::Lru, false), (Eviction::TwoQueue, true)] {
+            let mut pool = BufferPool::new(8, eviction);
+            // 1 and 2 are read, fall off the probationary queue, and come back: hot from then on
+            for id in [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 1, 2] {
+                read(&mut pool, id);
+            }
+            for id in 100..120 {
+                read(&mut pool, id);
+            }
+            assert_eq!(resident(&pool).starts_with(&[1, 2]), survives, "{eviction:?}");
+        }
+    }
+
+    #[test]
+    fn pinned_and_dirty_pages() {
+        let mut pool = BufferPool::new(2, Eviction::Lru);
+        let (one, _) = pool.admit(1, vec![1], true).unwrap();
+        let (two, _) = pool.admit(2, vec![2], false).unwrap();
+        // both pinned, there's nowhere to put a third
+        assert!(pool.admit(3, vec![3], false).is_none());
+
+        // 1 is older but still pinned, so 2 goes
+        pool.unpin(two);
+        let (three, evicted) = pool.admit(3, vec![3], false).unwrap();
+        assert_eq!((three, evicted), (two, None));
+        pool.unpin(three);
+
+        // and once 1 is let go, it goes back to the file on its way out
+        pool.unpin(one);
+        let (_, evicted) = pool.admit(4, vec![4], false).unwrap();
+        assert_eq!(evicted, Some((1, vec![1])));
+        assert_eq!((pool.stats.evictions, pool.stats.write_backs), (2, 1));
+    }
+
+    #[test]
+    fn dirty_pages_are_handed_over_once() {
+        let mut pool = BufferPool::new(4, Eviction::Clock);
+        for id in 1..=3 {
+            let (frame, _) = pool.admit(id, vec![0], false).unwrap();
+            pool.unpin(frame);
+        }
+        let frame = pool.resident(2).unwrap();
+        pool.overwrite(frame, vec![2]);
+        assert_eq!(pool.take_dirty(), [(2, &[2u8][..])]);
+        assert!(pool.take_dirty().is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::codec::Codec;
+use crate::disk::{PageNode, search};
+use crate::error::DiskError;
+use crate::pager::{PAGE_SIZE, PageId};
+
+// Copy-on-write trees never overwrite a page the last commit can reach. A change copies every page
+// it touches to a fresh one, and the parent it descended from is repointed at the copy; committing
+// writes the fresh pages out and then a header naming the new root, into whichever of the file's
+// two header pages holds the older one. A crash before that header is whole leaves the other in
+// charge, and the pages it reaches untouched.
+//
+// Pages a change copied away from are retired, not freed: snapshots, and on a crash the previous
+// header, may still reach them. They're handed out again once a header past the change that
+// retired them is on disk and no snapshot from before it is left.
+
+// how many snapshots are reading each commit, shared with snapshots on other threads
+type Readers = Arc<Mutex<BTreeMap<u64, usize>>>;
+
+fn lock(readers: &Readers) -> MutexGuard<'_, BTreeMap<u64, usize>> {
+    // the counts are only ever changed whole, so a panic elsewhere can't leave them half done
+    readers.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Shadows {
+    // allocated since the last commit, so no header reaches them yet and they're changed in place
+    fresh: HashSet<PageId>,
+    // copied away from since the last commit
+    retired: Vec<PageId>,
+    // retired by each commit, oldest first, until it's safe to hand them out again
+    released: VecDeque<(u64, Vec<PageId>)>,
+    free: Vec<PageId>,
+    // the newest commit whose header is known to be on disk
+    pub(crate) durable: u64,
+    readers: Readers,
+}
+
+impl Shadows {
+    // for a file opened at commit `txn`, which is on disk already
+    pub(crate) fn opened(txn: u64) -> Self {
+        Shadows { durable: txn, ..Shadows::default() }
+    }
+
+    pub(crate) fn is_fresh(&self, id: PageId) -> bool {
+        self.fresh.contains(&id)
+    }
+
+    pub(crate) fn allocate(&mut self, grow: impl FnOnce() -> PageId) -> PageId {
+        if self.free.is_empty() {
+            self.reclaim_released();
+        }
+        let id = self.free.pop().unwrap_or_else(grow);
+        self.fresh.insert(id);
+        id
+    }
+    pub(crate) fn retire(&mut self, id: PageId) {
+        // a page no commit has seen yet can go straight back out
+        if self.fresh.remove(&id) {
+            self.free.push(id);
+        } else {
+            self.retired.push(id);
+        }
+    }
+    pub(crate) fn committed(&mut self, txn: u64) {
+        self.fresh.clear();
+        self.released.push_back((txn, std::mem::take(&mut self.retired)));
+        self.reclaim_released();
+    }
+    // After a failed change, whatever it allocated is free again, but for pages past the last
+    // commit's end of the file, and what it retired the last commit still reaches.
+    pub(crate) fn roll_back(&mut self, pages: PageId) {
+        self.free.extend(self.fresh.drain());
+        self.free.retain(|&id| id < pages);
+        self.retired.clear();
+    }
+    // on open, everything the tree doesn't reach is free
+    pub(crate) fn reclaim(&mut self, pages: impl Iterator<Item = PageId>) {
+        self.free.extend(pages);
+    }
+    fn reclaim_released(&mut self) {
+        let oldest = lock(&self.readers).keys().next().copied().unwrap_or(u64::MAX);
+        while let Some(&(txn, _)) = self.released.front() {
+            if txn > self.durable || txn > oldest {
+                break;
+            }
+            let (_, pages) = self.released.pop_front().unwrap();
+            self.free.extend(pages);
+        }
+    }
+
+    pub(crate) fn snapshot<K, V>(&self, file: File, root: PageId, len: usize, txn: u64) -> Snapshot<K, V> {
+        *lock(&self.readers).entry(txn).or_default() += 1;
+        Snapshot { file, root, len, txn, readers: Arc::clone(&self.readers), entries: PhantomData }
+    }
+}
+
+/// A read-only view of a copy-on-write [`DiskBTree`](crate::DiskBTree) as
+/// it stood when [`snapshot`](crate::DiskBTree::snapshot) was taken,
+/// unaffected by the changes made to the tree since.
+///
+/// It can be sent to another thread and read there while the tree carries
+/// on changing. The pages it reads stay put for as long as it's alive, so a
+/// snapshot kept around keeps the file from reusing the space the tree has
+/// since moved on from.
+#[derive(Debug)]
+pub struct Snapshot<K, V> {
+    file: File,
+    root: PageId,
+    len: usize,
+    txn: u64,
+    readers: Readers,
+    entries: PhantomData<(K, V)>,
+}
+
+impl<K, V> Snapshot<K, V>
+where
+    K: Ord + Codec,
+    V: Codec,
+{
+    /// The number of entries the tree held.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// `true` if the tree held no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The value `key` had, if it was in the tree.
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<V>, DiskError>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let _span = tracing::trace_span!("snapshot.get", txn = self.txn).entered();
+        let mut node = self.load(self.root)?;
+        loop {
+            match search(&node.items, key) {
+                Ok(position) => return Ok(Some(node.items.swap_remove(position).value)),
+                Err(_) if node.is_leaf() => return Ok(None),
+                Err(position) => node = self.load(node.children[position])?,
+            }
+        }
+    }
+
+    /// `true` if `key` was in the tree.
+    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool, DiskError>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn load(&self, id: PageId) -> Result<PageNode<K, V>, DiskError> {
+        // straight from the file: every page a commit reaches is written out before its header
+        let mut page = vec![0; PAGE_SIZE];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(id * PAGE_SIZE as u64))?;
+        file.read_exact(&mut page)?;
+        PageNode::decode(id, &page)
+    }
+}
+
+impl<K, V> Drop for Snapshot<K, V> {
+    fn drop(&mut self) {
+        let mut readers = lock(&self.readers);
+        let count = readers.get_mut(&self.txn).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            readers.remove(&self.txn);
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::codec::Codec;
+use crate::error::DiskError;
+use crate::pager::{HEADER_SIZE, Header, PAGE_SIZE, PageId};
+
+// The write-ahead log sits beside the tree's file, and every changed page goes there rather than
+// into the file. A change ends with a commit record carrying the header that points at its pages,
+// so a crash part way through one leaves pages in the log that no commit ever claimed, and they're
+// ignored. Checkpointing copies the newest committed image of each page into the file and empties
+// the log. Records are checksummed: a torn write at the end of the log reads as the end of it.
+//
+//     page:   1  id: u64  page: [u8; PAGE_SIZE]  checksum: u64
+//     commit: 2  header: [u8; HEADER_SIZE]       checksum: u64
+
+const PAGE: u8 = 1;
+const COMMIT: u8 = 2;
+const PAGE_RECORD: usize = 1 + 8 + PAGE_SIZE + 8;
+const COMMIT_RECORD: usize = 1 + HEADER_SIZE + 8;
+
+/// How hard a [`DiskBTree`](crate::DiskBTree) works to make each change
+/// survive a power failure, as opposed to the process dying, which every
+/// mode survives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Every change waits for the disk before returning.
+    #[default]
+    EveryCommit,
+    /// Changes wait for the disk together, once every so many of them, so a
+    /// power failure can lose up to that many of the latest, never part of
+    /// one.
+    Group(usize),
+    /// Nothing waits for the disk but [`flush`](crate::DiskBTree::flush),
+    /// and a power failure can leave the file corrupt.
+    Never,
+}
+
+// where the log for the tree at `path` lives: beside it, with `-wal` on the end
+pub(crate) fn wal_path(path: &Path) -> PathBuf {
+    let mut wal = OsString::from(path.as_os_str());
+    wal.push("-wal");
+    wal.into()
+}
+
+#[derive(Debug)]
+pub(crate) struct Wal {
+    file: File,
+    // where the next record goes
+    end: u64,
+    // and where the last commit record ends
+    last_commit: u64,
+    // where the newest image of each page starts, once a commit has claimed it
+    committed: HashMap<PageId, u64>,
+    // and of pages written since the last commit
+    pending: HashMap<PageId, u64>,
+}
+
+impl Wal {
+    pub(crate) fn create(path: &Path) -> Result<Self, DiskError> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        Ok(Wal::new(file))
+    }
+
+    // Opens the log and reads back what it committed, handing back the header of its last commit.
+    // Anything after that, committed or not, is cut off.
+    pub(crate) fn open(path: &Path) -> Result<(Self, Option<Header>), DiskError> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let mut log = Vec::new();
+        file.read_to_end(&mut log)?;
+        let mut wal = Wal::new(file);
+        let header = wal.replay(&log);
+        wal.file.set_len(wal.end)?;
+        Ok((wal, header))
+    }
+
+    fn new(file: File) -> Self {
+        Wal { file, end: 0, last_commit: 0, committed: HashMap::new(), pending: HashMap::new() }
+    }
+
+    fn replay(&mut self, log: &[u8]) -> Option<Header> {
+        let mut header = None;
+        let mut at = 0;
+        while let Some(&tag) = log.get(at) {
+            let size = match tag {
+                PAGE => PAGE_RECORD,
+                COMMIT => COMMIT_RECORD,
+                _ => break,
+            };
+            let Some(record) = log.get(at..at + size) else { break };
+            let (body, mut sum) = record.split_at(size - 8);
+            if u64::decode(&mut sum) != Some(checksum(body)) {
+                break;
+            }
+            if tag == PAGE {
+                let id = u64::decode(&mut &body[1..]).unwrap();
+                self.pending.insert(id, at as u64);
+            } else {
+                let Ok(committed) = Header::decode(0, &body[1..]) else { break };
+                header = Some(committed);
+                self.committed.extend(self.pending.drain());
+                self.end = (at + size) as u64;
+                self.last_commit = self.end;
+            }
+            at += size;
+        }
+        self.pending.clear();
+        if header.is_some() {
+            tracing::debug!(pages = self.committed.len(), bytes = self.end, torn = log.len() as u64 - self.end, "replayed log");
+        }
+        header
+    }
+
+    // the newest image of page `id` in the log, committed or not
+    pub(crate) fn find(&self, id: PageId) -> Option<u64> {
+        self.pending.get(&id).or_else(|| self.committed.get(&id)).copied()
+    }
+    pub(crate) fn read_page(&mut self, offset: u64, page: &mut [u8]) -> Result<(), DiskError> {
+        self.file.seek(SeekFrom::Start(offset + 9))?;
+        self.file.read_exact(page)?;
+        Ok(())
+    }
+    // the pages a checkpoint has to copy into the file, and where to find them
+    pub(crate) fn committed(&self) -> Vec<(PageId, u64)> {
+        let mut pages: Vec<_> = self.committed.iter().map(|(&id, &offset)| (id, offset)).collect();
+        pages.sort_unstable();
+        pages
+    }
+    pub(crate) fn bytes(&self) -> u64 {
+        self.end
+    }
+
+    pub(crate) fn append_page(&mut self, id: PageId, page: &[u8]) -> Result<(), DiskError> {
+        let mut record = vec![PAGE];
+        id.encode(&mut record);
+        record.extend_from_slice(page);
+        let offset = self.append(record)?;
+        self.pending.insert(id, offset);
+        Ok(())
+    }
+    pub(crate) fn commit(&mut self, header: &Header) -> Result<(), DiskError> {
+        let mut record = vec![COMMIT];
+        record.extend(header.encode());
+        self.append(record)?;
+        self.last_commit = self.end;
+        self.committed.extend(self.pending.drain());
+        Ok(())
+    }
+    // Cuts off whatever a failed change wrote since the last commit. Until the file is cut, the
+    // records past the end are overwritten by the next change's, and never claimed by a commit.
+    pub(crate) fn roll_back(&mut self) -> Result<(), DiskError> {
+        self.pending.clear();
+        self.end = self.last_commit;
+        self.file.set_len(self.end)?;
+        Ok(())
+    }
+    fn append(&mut self, mut record: Vec<u8>) -> Result<u64, DiskError> {
+        checksum(&record).encode(&mut record);
+        let offset = self.end;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&record)?;
+        self.end += record.len() as u64;
+        Ok(offset)
+    }
+
+    pub(crate) fn sync(&mut self) -> Result<(), DiskError> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+    // after a checkpoint, once the file holds everything the log committed
+    pub(crate) fn truncate(&mut self) -> Result<(), DiskError> {
+        self.file.set_len(0)?;
+        self.committed.clear();
+        self.pending.clear();
+        self.end = 0;
+        self.last_commit = 0;
+        Ok(())
+    }
+}
+
+// 64-bit FNV-1a, plenty to tell a torn record from a whole one
+pub(crate) fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn header(len: usize) -> Header {
+        Header { copy_on_write: false, degree: 2, root: 1, len, pages: 3, free: 0, txn: len as u64 }
+    }
+
+    #[test]
+    fn replay_stops_at_the_last_whole_commit() {
+        let path = env::temp_dir().join(format!("btree-{}-replay.db-wal", process::id()));
+        let mut wal = Wal::create(&path).unwrap();
+        wal.append_page(1, &[1; PAGE_SIZE]).unwrap();
+        wal.append_page(2, &[2; PAGE_SIZE]).unwrap();
+        wal.commit(&header(1)).unwrap();
+        wal.append_page(1, &[3; PAGE_SIZE]).unwrap();
+        wal.commit(&header(2)).unwrap();
+        // a change that never committed, and then one whose commit is torn
+        wal.append_page(2, &[4; PAGE_SIZE]).unwrap();
+        wal.append_page(1, &[5; PAGE_SIZE]).unwrap();
+        wal.commit(&header(3)).unwrap();
+        let whole = wal.end;
+        wal.file.set_len(whole - 3).unwrap();
+        drop(wal);
+
+        let (mut wal, recovered) = Wal::open(&path).unwrap();
+        assert_eq!(recovered, Some(header(2)));
+        assert_eq!(wal.end, 3 * PAGE_RECORD as u64 + 2 * COMMIT_RECORD as u64);
+        assert_eq!(fs::metadata(&path).unwrap().len(), wal.end);
+        let mut page = vec![0; PAGE_SIZE];
+        for (id, byte) in [(1, 3), (2, 2)] {
+            let offset = wal.find(id).unwrap();
+            wal.read_page(offset, &mut page).unwrap();
+            assert!(page.iter().all(|&b| b == byte), "page {id}");
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rolling_back_drops_what_followed_the_last_commit() {
+        let path = env::temp_dir().join(format!("btree-{}-roll-back.db-wal", process::id()));
+        let mut wal = Wal::create(&path).unwrap();
+        wal.append_page(1, &[1; PAGE_SIZE]).unwrap();
+        wal.commit(&header(1)).unwrap();
+        let committed = wal.end;
+        wal.append_page(1, &[2; PAGE_SIZE]).unwrap();
+        wal.append_page(2, &[2; PAGE_SIZE]).unwrap();
+        wal.roll_back().unwrap();
+        assert_eq!((wal.end, wal.find(2)), (committed, None));
+        assert_eq!(fs::metadata(&path).unwrap().len(), committed);
+
+        // the next change picks up where the last commit left off
+        wal.append_page(2, &[3; PAGE_SIZE]).unwrap();
+        wal.commit(&header(2)).unwrap();
+        drop(wal);
+        let (mut wal, recovered) = Wal::open(&path).unwrap();
+        assert_eq!(recovered, Some(header(2)));
+        let mut page = vec![0; PAGE_SIZE];
+        for (id, byte) in [(1, 1), (2, 3)] {
+            let offset = wal.find(id).unwrap();
+            wal.read_page(offset, &mut page).unwrap();
+            assert!(page.iter().all(|&b| b == byte), "page {id}");
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_corrupt_record_ends_the_log() {
+        let path = env::temp_dir().join(format!("btree-{}-corrupt.db-wal", process::id()));
+        let mut wal = Wal::create(&path).unwrap();
+        wal.append_page(1, &[1; PAGE_SIZE]).unwrap();
+        wal.commit(&header(1)).unwrap();
+        drop(wal);
+        // a flipped bit in the page image
+        let mut log = fs::read(&path).unwrap();
+        log[100] ^= 1;
+        fs::write(&path, log).unwrap();
+
+        let (wal, recovered) = Wal::open(&path).unwrap();
+        assert_eq!((recovered, wal.end), (None, 0));
+        assert_eq!(wal.find(1), None);
+        fs::remove_file(&path).unwrap();
+    }
+}